@@ -0,0 +1,17 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE posts ADD tags VARCHAR NOT NULL DEFAULT '';
+
+UPDATE posts
+SET tags = t.joined
+FROM (
+    SELECT post_tags.post_id, string_agg(tags.name, '|' ORDER BY tags.name) AS joined
+    FROM post_tags
+    JOIN tags ON tags.id = post_tags.tag_id
+    GROUP BY post_tags.post_id
+) AS t
+WHERE posts.id = t.post_id;
+
+ALTER TABLE posts ALTER COLUMN tags DROP DEFAULT;
+
+DROP TABLE post_tags;
+DROP TABLE tags;
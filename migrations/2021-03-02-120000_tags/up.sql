@@ -0,0 +1,25 @@
+-- Your SQL goes here
+CREATE TABLE tags (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL UNIQUE
+);
+
+CREATE TABLE post_tags (
+    post_id INT NOT NULL REFERENCES posts (id) ON DELETE CASCADE,
+    tag_id INT NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+    PRIMARY KEY (post_id, tag_id)
+);
+
+CREATE INDEX post_tags_tag_id_idx ON post_tags (tag_id);
+
+INSERT INTO tags (name)
+SELECT DISTINCT TRIM(t.name)
+FROM posts, regexp_split_to_table(posts.tags, '\|') AS t(name)
+WHERE TRIM(t.name) <> '';
+
+INSERT INTO post_tags (post_id, tag_id)
+SELECT DISTINCT posts.id, tags.id
+FROM posts, regexp_split_to_table(posts.tags, '\|') AS t(name)
+JOIN tags ON tags.name = TRIM(t.name);
+
+ALTER TABLE posts DROP COLUMN tags;
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER tags_search_vector_update ON tags;
+DROP FUNCTION tags_search_vector_update();
+DROP TRIGGER post_tags_search_vector_update ON post_tags;
+DROP FUNCTION post_tags_search_vector_update();
+DROP TRIGGER posts_search_vector_update ON posts;
+DROP FUNCTION posts_search_vector_update();
+DROP FUNCTION posts_search_vector(INT, VARCHAR, VARCHAR);
+ALTER TABLE posts DROP COLUMN search_vector;
@@ -0,0 +1,64 @@
+-- Your SQL goes here
+-- The 'simple' configuration does no stemming, which keeps Korean and
+-- mixed-language posts searchable.
+ALTER TABLE posts ADD search_vector TSVECTOR NOT NULL DEFAULT '';
+
+CREATE FUNCTION posts_search_vector(post_id INT, title VARCHAR, body VARCHAR)
+RETURNS TSVECTOR AS $$
+    SELECT setweight(to_tsvector('simple', coalesce(title, '')), 'A')
+        || setweight(to_tsvector('simple', coalesce((
+            SELECT string_agg(tags.name, ' ')
+            FROM post_tags
+            JOIN tags ON tags.id = post_tags.tag_id
+            WHERE post_tags.post_id = posts_search_vector.post_id
+        ), '')), 'B')
+        || setweight(to_tsvector('simple', coalesce(body, '')), 'C');
+$$ LANGUAGE sql STABLE;
+
+CREATE FUNCTION posts_search_vector_update() RETURNS trigger AS $$
+BEGIN
+    NEW.search_vector := posts_search_vector(NEW.id, NEW.title, NEW.body);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER posts_search_vector_update
+BEFORE INSERT OR UPDATE OF title, body ON posts
+FOR EACH ROW EXECUTE PROCEDURE posts_search_vector_update();
+
+CREATE FUNCTION post_tags_search_vector_update() RETURNS trigger AS $$
+DECLARE
+    changed INT;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD.post_id;
+    ELSE
+        changed := NEW.post_id;
+    END IF;
+    UPDATE posts
+    SET search_vector = posts_search_vector(posts.id, posts.title, posts.body)
+    WHERE posts.id = changed;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER post_tags_search_vector_update
+AFTER INSERT OR DELETE ON post_tags
+FOR EACH ROW EXECUTE PROCEDURE post_tags_search_vector_update();
+
+CREATE FUNCTION tags_search_vector_update() RETURNS trigger AS $$
+BEGIN
+    UPDATE posts
+    SET search_vector = posts_search_vector(posts.id, posts.title, posts.body)
+    WHERE posts.id IN (SELECT post_id FROM post_tags WHERE tag_id = NEW.id);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER tags_search_vector_update
+AFTER UPDATE OF name ON tags
+FOR EACH ROW EXECUTE PROCEDURE tags_search_vector_update();
+
+UPDATE posts SET search_vector = posts_search_vector(id, title, body);
+
+CREATE INDEX posts_search_vector_idx ON posts USING GIN (search_vector);
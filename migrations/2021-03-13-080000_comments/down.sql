@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE comments;
+ALTER TABLE posts DROP COLUMN comments_closed;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+ALTER TABLE posts ADD comments_closed BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE comments (
+    id SERIAL PRIMARY KEY,
+    post_id INT NOT NULL REFERENCES posts (id) ON DELETE CASCADE,
+    parent_id INT REFERENCES comments (id) ON DELETE CASCADE,
+    author INT REFERENCES users (id) ON DELETE SET NULL,
+    author_name VARCHAR,
+    body VARCHAR NOT NULL,
+    status INT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    modified_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX comments_post_id_idx ON comments (post_id);
+CREATE INDEX comments_status_idx ON comments (status);
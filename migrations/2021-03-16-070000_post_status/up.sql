@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- 0 = draft, 1 = scheduled, 2 = published, 3 = archived
+ALTER TABLE posts ADD status INT NOT NULL DEFAULT 2;
+ALTER TABLE posts ADD published_at TIMESTAMP;
+UPDATE posts SET published_at = created_at;
+ALTER TABLE posts ALTER status DROP DEFAULT;
+
+CREATE INDEX posts_status_published_at_idx ON posts (status, published_at);
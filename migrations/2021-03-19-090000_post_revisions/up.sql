@@ -0,0 +1,20 @@
+-- Your SQL goes here
+CREATE TABLE post_revisions (
+    id SERIAL PRIMARY KEY,
+    post_id INT NOT NULL REFERENCES posts (id) ON DELETE CASCADE,
+    revision INT NOT NULL,
+    title VARCHAR NOT NULL,
+    body VARCHAR NOT NULL,
+    tags TEXT[] NOT NULL,
+    editor INT REFERENCES users (id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (post_id, revision)
+);
+
+INSERT INTO post_revisions (post_id, revision, title, body, tags, editor, created_at)
+SELECT posts.id, 1, posts.title, posts.body,
+       COALESCE((SELECT array_agg(tags.name ORDER BY tags.name)
+                 FROM post_tags JOIN tags ON tags.id = post_tags.tag_id
+                 WHERE post_tags.post_id = posts.id), '{}'),
+       posts.author, posts.modified_at
+FROM posts;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE posts ADD revision INT NOT NULL DEFAULT 1;
+UPDATE posts SET revision = (
+    SELECT COALESCE(MAX(post_revisions.revision), 1)
+    FROM post_revisions WHERE post_revisions.post_id = posts.id);
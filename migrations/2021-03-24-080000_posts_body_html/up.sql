@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- Rendered and sanitized from `body` whenever a post is saved. NULL for posts
+-- saved before rendering was added; those are rendered on the fly until their
+-- next edit.
+ALTER TABLE posts ADD body_html VARCHAR;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE post_slug_redirects;
+ALTER TABLE posts DROP COLUMN slug;
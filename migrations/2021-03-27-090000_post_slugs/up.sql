@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- Existing posts keep their id as slug until their title changes.
+ALTER TABLE posts ADD slug VARCHAR;
+UPDATE posts SET slug = id::TEXT;
+ALTER TABLE posts ALTER slug SET NOT NULL;
+ALTER TABLE posts ADD CONSTRAINT posts_slug_key UNIQUE (slug);
+
+-- Slugs a post was known by before, so old permalinks keep working.
+CREATE TABLE post_slug_redirects (
+    slug VARCHAR PRIMARY KEY,
+    post_id INT NOT NULL REFERENCES posts (id) ON DELETE CASCADE
+);
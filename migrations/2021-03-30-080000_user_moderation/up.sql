@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- A banned account cannot log in or use its tokens until `banned_until`,
+-- or for good when that is NULL.
+ALTER TABLE users ADD banned BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD banned_until TIMESTAMP;
+ALTER TABLE users ADD password_reset_required BOOLEAN NOT NULL DEFAULT FALSE;
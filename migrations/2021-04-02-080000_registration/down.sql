@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE invites;
+DROP INDEX users_email_key;
+ALTER TABLE users DROP CONSTRAINT users_username_key;
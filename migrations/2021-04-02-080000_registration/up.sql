@@ -0,0 +1,41 @@
+-- Your SQL goes here
+
+-- Registration never checked for taken usernames, so duplicates may exist.
+-- The oldest account keeps the name; the others get their id appended.
+UPDATE users SET username = users.username || '_' || users.id
+FROM users AS older
+WHERE older.username = users.username AND older.id < users.id;
+
+-- Emails cannot be renamed on the owner's behalf, so refuse to migrate
+-- until an admin has resolved the duplicates by hand.
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(format('%s (users %s)', email, ids), ', ')
+    INTO duplicates
+    FROM (
+        SELECT lower(email) AS email, string_agg(id::TEXT, ', ' ORDER BY id) AS ids
+        FROM users
+        GROUP BY lower(email)
+        HAVING COUNT(*) > 1
+    ) AS taken;
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'emails registered more than once, change all but one of each: %',
+            duplicates;
+    END IF;
+END
+$$;
+
+ALTER TABLE users ADD CONSTRAINT users_username_key UNIQUE (username);
+-- Emails are compared case-insensitively.
+CREATE UNIQUE INDEX users_email_key ON users (lower(email));
+
+CREATE TABLE invites (
+    code VARCHAR PRIMARY KEY,
+    created_by INT REFERENCES users (id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    used_by INT REFERENCES users (id) ON DELETE SET NULL,
+    used_at TIMESTAMP
+);
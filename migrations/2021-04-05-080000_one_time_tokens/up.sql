@@ -0,0 +1,15 @@
+-- Your SQL goes here
+ALTER TABLE users ADD email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- purpose: 0 = verify email, 1 = reset password
+CREATE TABLE one_time_tokens (
+    id VARCHAR PRIMARY KEY,
+    user_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    purpose INT NOT NULL,
+    email VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX one_time_tokens_user_id_idx ON one_time_tokens (user_id, purpose);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE posts ADD permission INT NOT NULL DEFAULT 0;
+UPDATE posts SET permission = 1 WHERE visibility <> 0;
+ALTER TABLE posts DROP COLUMN share_token;
+ALTER TABLE posts DROP COLUMN password_hash;
+ALTER TABLE posts DROP COLUMN visibility;
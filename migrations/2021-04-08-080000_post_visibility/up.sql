@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- 0 = public, 1 = unlisted, 2 = members, 3 = private, 4 = password
+ALTER TABLE posts ADD visibility INT NOT NULL DEFAULT 0;
+ALTER TABLE posts ADD password_hash VARCHAR;
+ALTER TABLE posts ADD share_token VARCHAR UNIQUE;
+-- Posts limited to an account level were only readable by a few people.
+UPDATE posts SET visibility = 3 WHERE permission <> 0;
+ALTER TABLE posts DROP COLUMN permission;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE users ADD bio VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE users ADD avatar_url VARCHAR;
+ALTER TABLE users ADD links TEXT[] NOT NULL DEFAULT '{}';
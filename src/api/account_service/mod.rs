@@ -1,20 +1,20 @@
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::{get, post, web};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
-pub mod errors;
-
+use crate::api::errors::ApiError;
+use crate::api::{created, no_content, ok, paging, ApiResult};
+use crate::config::RegistrationMode;
 use crate::db;
-use crate::db::models::AccountLevel;
+use crate::db::models::{AccountLevel, Capability, Invite, NewInvite, TokenPurpose, User};
+use crate::middlewares::auth::{
+    issue_one_time_token, random_hex, redeem_one_time_token, refresh_session, require,
+    start_session, AuthenticatedSession, AuthenticatedUser, OptionalUser, TokenPair,
+};
+use crate::middlewares::mailer::{self, BoxedMailer, Mail};
+use crate::middlewares::postgresql::{run, DbPool};
 use crate::CONFIG;
-use errors::AccountError;
-// use hmac::{Hmac, NewMac};
-use jwt_simple::prelude::*;
-
-#[derive(Clone, Serialize, Deserialize)]
-pub struct ResponseBlock<T> {
-    pub status: bool,
-    pub body: Option<T>,
-}
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Ping {
@@ -29,8 +29,14 @@ pub struct LoginForm {
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct LoginResponse {
-    pub result: AccountError,
-    pub token: Option<String>, // JWT token
+    pub token: String, // JWT token
+    pub refresh_token: String,
+    pub expires_in: u64, // seconds until `token` expires
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RefreshForm {
+    pub refresh_token: String,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -39,11 +45,51 @@ pub struct RegisterForm {
     pub pass: String,
     pub email: String,
     pub nickname: String,
+    /// Required while registration is invite-only, ignored otherwise.
+    pub invite: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct VerifyEmailForm {
+    pub token: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct PasswordResetRequestForm {
+    pub email: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ResetPasswordForm {
+    pub token: String,
+    pub new_pass: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ChangePasswordForm {
+    pub pass: String,
+    pub new_pass: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ChangeEmailForm {
+    pub pass: String,
+    pub email: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct EditProfileForm {
+    pub nickname: String,
+    /// Fields left out keep their current value. An empty `avatar_url`
+    /// removes the avatar.
+    pub bio: Option<String>,
+    pub avatar_url: Option<String>,
+    pub links: Option<Vec<String>>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct RegisterResponse {
-    pub result: AccountError,
+    pub pk: i64,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -52,158 +98,743 @@ pub struct AccountToken {
 }
 
 #[derive(Clone, Deserialize)]
-pub struct AuthRequest {
-    pub token: String,
+pub struct InfoRequest {
+    pub pk: i32,
 }
 
-#[derive(Clone, Deserialize)]
-pub struct InfoRequest {
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InfoResponse {
+    pub pk: i64,
+    pub username: String,
+    pub nickname: String,
+    /// Only shown to the user themselves and to admins.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    pub bio: String,
+    pub avatar_url: Option<String>,
+    pub links: Vec<String>,
+    pub level: AccountLevel,
+    pub capabilities: Vec<Capability>,
+}
+
+impl From<User> for InfoResponse {
+    fn from(user: User) -> Self {
+        let level = user.level();
+        InfoResponse {
+            pk: user.id as i64,
+            username: user.username,
+            nickname: user.nickname,
+            email: Some(user.email),
+            email_verified: Some(user.email_verified),
+            bio: user.bio,
+            avatar_url: user.avatar_url,
+            links: user.links,
+            level,
+            capabilities: level.capabilities().to_vec(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SetLevelForm {
     pub pk: i32,
+    pub level: AccountLevel,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
-pub struct AsRequest<T> {
-    pub token: String,
-    pub body: T,
+pub struct LevelInfo {
+    pub level: AccountLevel,
+    pub capabilities: Vec<Capability>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
-pub struct InfoResponse {
+pub struct LevelsResponse {
+    pub levels: Vec<LevelInfo>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct UsersForm {
+    /// Matched against usernames, nicknames and emails.
+    pub q: Option<String>,
+    pub start: i64,
+    /// Capped at `blog.max_page_size`.
+    pub count: i64,
+}
+
+/// A user as seen by admins.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ManagedUser {
     pub pk: i64,
     pub username: String,
     pub nickname: String,
     pub email: String,
+    pub email_verified: bool,
     pub level: AccountLevel,
+    pub banned: bool,
+    pub banned_until: Option<NaiveDateTime>,
+    pub password_reset_required: bool,
+}
+
+impl From<User> for ManagedUser {
+    fn from(user: User) -> Self {
+        ManagedUser {
+            pk: user.id as i64,
+            level: user.level(),
+            banned: user.is_banned(),
+            banned_until: user.banned_until.filter(|_| user.banned),
+            username: user.username,
+            nickname: user.nickname,
+            email: user.email,
+            email_verified: user.email_verified,
+            password_reset_required: user.password_reset_required,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UsersResponse {
+    pub count: i64,
+    pub users: Vec<ManagedUser>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct BanUserForm {
+    pub pk: i32,
+    /// End of the ban in UTC. Bans without one last until lifted.
+    pub until: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct UserForm {
+    pub pk: i32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InvitesResponse {
+    pub invites: Vec<Invite>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct DeleteInviteForm {
+    pub code: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct DeleteUserForm {
+    pub pk: i32,
+    /// Who gets the user's posts. They are deleted along with the user when
+    /// not given.
+    pub reassign_to: Option<i32>,
 }
 
 #[get("/api/account_service/ping")]
-pub async fn ping() -> web::Json<ResponseBlock<Ping>> {
-    web::Json(ResponseBlock {
-        status: true,
-        body: Some(Ping {
-            reply: String::from("pong!"),
-        }),
+pub async fn ping() -> ApiResult {
+    ok(Ping {
+        reply: String::from("pong!"),
     })
 }
 
 #[get("/api/account_service/info")]
-pub async fn info(web::Query(parms): web::Query<AuthRequest>) -> HttpResponse {
-    let config = CONFIG.clone();
-    let key = HS256Key::from_bytes(config.secret.secret.as_bytes());
-    let claims_wrapped = key.verify_token::<AccountToken>(&parms.token, None);
-    let json = if let Ok(claims) = claims_wrapped {
-        if let Ok(user) = db::find_user(claims.custom.pk) {
-            Some(InfoResponse {
-                pk: claims.custom.pk as i64,
-                username: user.username,
-                nickname: user.nickname,
-                email: user.email,
-                level: match user.permission {
-                    1 => AccountLevel::Admin,
-                    _ => AccountLevel::Default,
-                },
-            })
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(ResponseBlock {
-            status: json.is_some(),
-            body: json,
-        })
+pub async fn info(AuthenticatedUser(user): AuthenticatedUser) -> ApiResult {
+    ok(InfoResponse::from(user))
 }
 
 #[get("/api/account_service/get_user")]
-pub async fn get_user(web::Query(parms): web::Query<InfoRequest>) -> HttpResponse {
-    let json = if let Ok(user) = db::find_user(parms.pk) {
-        Some(InfoResponse {
-            pk: parms.pk as i64,
-            username: user.username,
-            nickname: user.nickname,
-            email: user.email,
-            level: match user.permission {
-                1 => AccountLevel::Admin,
-                _ => AccountLevel::Default,
-            },
-        })
-    } else {
-        None
-    };
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(ResponseBlock {
-            status: json.is_some(),
-            body: json,
-        })
+pub async fn get_user(
+    pool: web::Data<DbPool>,
+    OptionalUser(viewer): OptionalUser,
+    web::Query(parms): web::Query<InfoRequest>,
+) -> ApiResult {
+    let pk = parms.pk;
+    let user = run(&pool, move |db| db::find_user(db, pk).optional())
+        .await?
+        .ok_or(ApiError::UserNotFound)?;
+    let private =
+        viewer.is_some_and(|viewer| viewer.id == pk || viewer.can(Capability::ManageUsers));
+    let mut response = InfoResponse::from(user);
+    if !private {
+        response.email = None;
+        response.email_verified = None;
+    }
+    ok(response)
 }
 
-#[post("/api/account_service/login")]
-pub async fn login(form: web::Json<LoginForm>) -> HttpResponse {
-    let config = CONFIG.clone();
-    let (err, pk) =
-        db::login(&form.username, &form.pass).unwrap_or((AccountError::DatabaseError, -1));
-    let json = if err == AccountError::Nothing {
+impl From<TokenPair> for LoginResponse {
+    fn from(pair: TokenPair) -> Self {
         LoginResponse {
-            result: err,
-            token: Some({
-                let key = HS256Key::from_bytes(config.secret.secret.as_bytes());
-                let auth = AccountToken { pk };
-                let claims = Claims::with_custom_claims(auth, Duration::from_days(1));
-                key.authenticate(claims).unwrap()
-            }),
+            token: pair.access_token,
+            refresh_token: pair.refresh_token,
+            expires_in: CONFIG.security.access_token_ttl_minutes * 60,
         }
+    }
+}
+
+/// Checks a username and password, failing if the account may not log in.
+async fn check_login(pool: &DbPool, username: String, pass: String) -> Result<User, ApiError> {
+    let user = run(pool, move |db| db::login(db, &username, &pass))
+        .await?
+        .ok_or(ApiError::InvalidCredentials)?;
+    if user.is_banned() {
+        return Err(ApiError::AccountBanned);
+    }
+    Ok(user)
+}
+
+#[post("/api/account_service/login")]
+pub async fn login(pool: web::Data<DbPool>, form: web::Json<LoginForm>) -> ApiResult {
+    let form = form.into_inner();
+    let user = check_login(&pool, form.username, form.pass).await?;
+    if user.password_reset_required {
+        return Err(ApiError::PasswordResetRequired);
+    }
+    let pair = run(&pool, move |db| start_session(db, user.id)).await?;
+    ok(LoginResponse::from(pair))
+}
+
+#[post("/api/account_service/refresh")]
+pub async fn refresh(pool: web::Data<DbPool>, form: web::Json<RefreshForm>) -> ApiResult {
+    let form = form.into_inner();
+    let pair = run(&pool, move |db| refresh_session(db, &form.refresh_token))
+        .await?
+        .ok_or(ApiError::InvalidRefreshToken)?;
+    ok(LoginResponse::from(pair))
+}
+
+/// Revokes the session of the access token used to make this request.
+#[post("/api/account_service/logout")]
+pub async fn logout(pool: web::Data<DbPool>, session: AuthenticatedSession) -> ApiResult {
+    run(&pool, move |db| db::revoke_refresh_token(db, &session.id)).await?;
+    no_content()
+}
+
+/// Revokes every session of the caller, including the current one.
+#[post("/api/account_service/logout_all")]
+pub async fn logout_all(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> ApiResult {
+    run(&pool, move |db| db::revoke_user_refresh_tokens(db, user.id)).await?;
+    no_content()
+}
+
+/// Creates an account at the default level and mails a link to verify its
+/// address. Depending on `registration.mode`, this needs an invite code or
+/// is not possible at all.
+#[post("/api/account_service/register")]
+pub async fn register(
+    pool: web::Data<DbPool>,
+    mailer: web::Data<BoxedMailer>,
+    form: web::Json<RegisterForm>,
+) -> ApiResult {
+    let mut form = form.into_inner();
+    let invite = match CONFIG.registration.mode {
+        RegistrationMode::Open => None,
+        RegistrationMode::Invite => Some(form.invite.take().ok_or(ApiError::InvalidInvite)?),
+        RegistrationMode::Closed => return Err(ApiError::RegistrationClosed),
+    };
+    let user = run(&pool, move |db| {
+        db::register(
+            db,
+            &form.username,
+            &form.pass,
+            &form.email,
+            &form.nickname,
+            AccountLevel::Default,
+            invite.as_deref(),
+        )
+    })
+    .await?
+    .ok_or(ApiError::InvalidInvite)?;
+    if let Err(e) = send_verification(&pool, &mailer, &user).await {
+        log::error!(
+            "could not send verification mail to user {}: {}",
+            user.id,
+            e
+        );
+    }
+    created(RegisterResponse { pk: user.id as i64 })
+}
+
+/// Every account level and what it allows.
+#[get("/api/account_service/levels")]
+pub async fn levels() -> ApiResult {
+    ok(LevelsResponse {
+        levels: AccountLevel::ALL
+            .iter()
+            .map(|&level| LevelInfo {
+                level,
+                capabilities: level.capabilities().to_vec(),
+            })
+            .collect(),
+    })
+}
+
+/// Assigns an account level to another user.
+#[post("/api/account_service/set_level")]
+pub async fn set_level(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<SetLevelForm>,
+) -> ApiResult {
+    require(&user, Capability::ManageUsers)?;
+    let SetLevelForm { pk, level } = form.into_inner();
+    not_self(&user, pk)?;
+    if run(&pool, move |db| db::set_user_level(db, pk, level)).await? == 0 {
+        return Err(ApiError::UserNotFound);
+    }
+    no_content()
+}
+
+/// Fails when `pk` is the caller. Admins cannot demote, ban or delete
+/// themselves, so the last admin cannot lock everyone out by accident.
+fn not_self(user: &User, pk: i32) -> Result<(), ApiError> {
+    if pk == user.id {
+        Err(ApiError::InvalidInput(String::from(
+            "cannot be applied to your own account",
+        )))
     } else {
-        LoginResponse {
-            result: err,
-            token: None,
+        Ok(())
+    }
+}
+
+/// Users matching `q`, oldest accounts first.
+#[get("/api/account_service/users")]
+pub async fn users(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    web::Query(parms): web::Query<UsersForm>,
+) -> ApiResult {
+    require(&user, Capability::ManageUsers)?;
+    let (start, count) = paging(parms.start, parms.count)?;
+    let (count, list) = run(&pool, move |db| {
+        db::search_users(db, parms.q.as_deref(), start, count)
+    })
+    .await?;
+    ok(UsersResponse {
+        count,
+        users: list.into_iter().map(ManagedUser::from).collect(),
+    })
+}
+
+/// Bans a user, ending their sessions. They can neither log in nor use
+/// tokens issued before the ban until it ends or is lifted.
+#[post("/api/account_service/ban_user")]
+pub async fn ban_user(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<BanUserForm>,
+) -> ApiResult {
+    require(&user, Capability::ManageUsers)?;
+    let BanUserForm { pk, until } = form.into_inner();
+    not_self(&user, pk)?;
+    if run(&pool, move |db| db::ban_user(db, pk, until)).await? == 0 {
+        return Err(ApiError::UserNotFound);
+    }
+    no_content()
+}
+
+#[post("/api/account_service/unban_user")]
+pub async fn unban_user(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<UserForm>,
+) -> ApiResult {
+    require(&user, Capability::ManageUsers)?;
+    let pk = form.pk;
+    if run(&pool, move |db| db::unban_user(db, pk)).await? == 0 {
+        return Err(ApiError::UserNotFound);
+    }
+    no_content()
+}
+
+/// Ends a user's sessions and mails them a reset link. They cannot log in
+/// again until they have set a new password through it, so someone who
+/// only knows the old password cannot take over the account.
+#[post("/api/account_service/force_password_reset")]
+pub async fn force_password_reset(
+    pool: web::Data<DbPool>,
+    mailer: web::Data<BoxedMailer>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<UserForm>,
+) -> ApiResult {
+    require(&user, Capability::ManageUsers)?;
+    let pk = form.pk;
+    not_self(&user, pk)?;
+    let target = run(&pool, move |db| {
+        if db::require_password_reset(db, pk)? == 0 {
+            return Ok(None);
         }
-    };
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(ResponseBlock {
-            status: true,
-            body: Some(json),
-        })
+        db::find_user(db, pk).optional()
+    })
+    .await?
+    .ok_or(ApiError::UserNotFound)?;
+    send_password_reset(&pool, &mailer, target).await?;
+    no_content()
 }
 
-#[post("/api/account_service/register")]
-pub async fn register(form: web::Json<RegisterForm>) -> HttpResponse {
-    let mut result = AccountError::Nothing;
-    let v1 = db::by_username(&form.username).unwrap_or(vec![]);
-    let v2 = db::by_email(&form.email).unwrap_or(vec![]);
-    if !v1.is_empty() {
-        result = AccountError::UsernameAlreadyExists;
-    }
-    if !v2.is_empty() {
-        result = AccountError::EmailAlreadyExists;
-    }
-    if v1.is_empty() && v2.is_empty() {
-        if let Ok(cnt) = db::count_users() {
-            if let Err(_) = db::register(
-                &form.username,
-                &form.pass,
-                &form.email,
-                &form.nickname,
-                if cnt == 0 {
-                    AccountLevel::Admin
-                } else {
-                    AccountLevel::Default
-                },
-            ) {
-                result = AccountError::DatabaseError;
-            }
-        } else {
-            result = AccountError::DatabaseError;
+#[post("/api/account_service/delete_user")]
+pub async fn delete_user(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<DeleteUserForm>,
+) -> ApiResult {
+    require(&user, Capability::ManageUsers)?;
+    let DeleteUserForm { pk, reassign_to } = form.into_inner();
+    not_self(&user, pk)?;
+    if let Some(to) = reassign_to {
+        if to == pk {
+            return Err(ApiError::InvalidInput(String::from(
+                "cannot reassign posts to the deleted user",
+            )));
         }
+        run(&pool, move |db| db::find_user(db, to).optional())
+            .await?
+            .ok_or(ApiError::UserNotFound)?;
+    }
+    if run(&pool, move |db| db::delete_user(db, pk, reassign_to)).await? == 0 {
+        return Err(ApiError::UserNotFound);
+    }
+    no_content()
+}
+
+/// Makes a single-use invite code for registering while registration is
+/// invite-only.
+#[post("/api/account_service/new_invite")]
+pub async fn new_invite(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> ApiResult {
+    require(&user, Capability::ManageUsers)?;
+    let expires_at = Utc::now().naive_utc() + Duration::days(CONFIG.registration.invite_ttl_days);
+    let invite = run(&pool, move |db| {
+        db::create_invite(
+            db,
+            &NewInvite {
+                code: &random_hex(12),
+                created_by: user.id,
+                expires_at,
+            },
+        )
+    })
+    .await?;
+    created(invite)
+}
+
+#[get("/api/account_service/invites")]
+pub async fn invites(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> ApiResult {
+    require(&user, Capability::ManageUsers)?;
+    let list = run(&pool, db::invites).await?;
+    ok(InvitesResponse { invites: list })
+}
+
+/// Withdraws an invite that has not been used yet.
+#[post("/api/account_service/delete_invite")]
+pub async fn delete_invite(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<DeleteInviteForm>,
+) -> ApiResult {
+    require(&user, Capability::ManageUsers)?;
+    let code = form.into_inner().code;
+    if run(&pool, move |db| db::delete_invite(db, &code)).await? == 0 {
+        return Err(ApiError::InvalidInvite);
+    }
+    no_content()
+}
+
+/// A link into the frontend page at `path` that completes a mailed flow.
+fn mail_link(path: &str, token: &str) -> String {
+    format!(
+        "{}/{}",
+        CONFIG.blog.url.trim_end_matches('/'),
+        path.replace("{token}", token).trim_start_matches('/')
+    )
+}
+
+async fn send_verification(
+    pool: &DbPool,
+    mailer: &web::Data<BoxedMailer>,
+    user: &User,
+) -> Result<(), ApiError> {
+    let hours = CONFIG.mail.verify_email_ttl_hours;
+    let (id, email) = (user.id, user.email.clone());
+    let token = run(pool, move |db| {
+        issue_one_time_token(
+            db,
+            id,
+            TokenPurpose::VerifyEmail,
+            &email,
+            hours as u64 * 3600,
+        )
+    })
+    .await?;
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: format!("Verify your email for {}", CONFIG.blog.name),
+        body: format!(
+            "Hi {},\n\nOpen this link to verify your email address:\n{}\n\n\
+             The link expires in {} hours. If you did not sign up, ignore this mail.\n",
+            user.nickname,
+            mail_link(&CONFIG.mail.verify_email_path, &token),
+            hours
+        ),
+    };
+    mailer::send(mailer, mail).await.map_err(|e| {
+        log::error!("{}", e);
+        ApiError::MailFailed
+    })
+}
+
+/// Mails the caller a new link to verify their address, unless it already
+/// is verified.
+#[post("/api/account_service/request_verification")]
+pub async fn request_verification(
+    pool: web::Data<DbPool>,
+    mailer: web::Data<BoxedMailer>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> ApiResult {
+    if !user.email_verified {
+        send_verification(&pool, &mailer, &user).await?;
+    }
+    no_content()
+}
+
+#[post("/api/account_service/verify_email")]
+pub async fn verify_email(pool: web::Data<DbPool>, form: web::Json<VerifyEmailForm>) -> ApiResult {
+    let token = form.into_inner().token;
+    let verified = run(&pool, move |db| {
+        db.transaction(
+            || match redeem_one_time_token(db, &token, TokenPurpose::VerifyEmail)? {
+                Some((id, email)) => db::set_email_verified(db, id, &email),
+                None => Ok(0),
+            },
+        )
+    })
+    .await?;
+    if verified == 0 {
+        return Err(ApiError::InvalidToken);
+    }
+    no_content()
+}
+
+/// Mails a password reset link to the account with `email`. Answers the
+/// same whether or not there is one, so it cannot be used to probe for
+/// accounts.
+#[post("/api/account_service/request_password_reset")]
+pub async fn request_password_reset(
+    pool: web::Data<DbPool>,
+    mailer: web::Data<BoxedMailer>,
+    form: web::Json<PasswordResetRequestForm>,
+) -> ApiResult {
+    let email = form.into_inner().email;
+    if let Some(user) = run(&pool, move |db| db::user_by_email(db, &email)).await? {
+        send_password_reset(&pool, &mailer, user).await?;
+    }
+    no_content()
+}
+
+/// Mails `user` a link to choose a new password. A mail that cannot be
+/// sent is only logged; the user can ask for another one.
+async fn send_password_reset(
+    pool: &DbPool,
+    mailer: &web::Data<BoxedMailer>,
+    user: User,
+) -> Result<(), ApiError> {
+    let minutes = CONFIG.mail.reset_password_ttl_minutes;
+    let (id, email) = (user.id, user.email.clone());
+    let token = run(pool, move |db| {
+        issue_one_time_token(
+            db,
+            id,
+            TokenPurpose::ResetPassword,
+            &email,
+            minutes as u64 * 60,
+        )
+    })
+    .await?;
+    let mail = Mail {
+        to: user.email,
+        subject: format!("Reset your password for {}", CONFIG.blog.name),
+        body: format!(
+            "Hi {},\n\nOpen this link to choose a new password:\n{}\n\n\
+             The link expires in {} minutes. If you did not ask for this, ignore this mail.\n",
+            user.nickname,
+            mail_link(&CONFIG.mail.reset_password_path, &token),
+            minutes
+        ),
+    };
+    if let Err(e) = mailer::send(mailer, mail).await {
+        log::error!("could not send password reset mail to user {}: {}", id, e);
+    }
+    Ok(())
+}
+
+/// Sets a new password with a token from a reset mail and ends every
+/// session of the account.
+#[post("/api/account_service/reset_password")]
+pub async fn reset_password(
+    pool: web::Data<DbPool>,
+    form: web::Json<ResetPasswordForm>,
+) -> ApiResult {
+    let form = form.into_inner();
+    if form.new_pass.is_empty() {
+        return Err(ApiError::InvalidInput(String::from(
+            "new_pass must not be empty",
+        )));
     }
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(ResponseBlock {
-            status: true,
-            body: Some(RegisterResponse { result }),
+    let reset = run(&pool, move |db| {
+        db.transaction(|| {
+            match redeem_one_time_token(db, &form.token, TokenPurpose::ResetPassword)? {
+                Some((id, email)) => {
+                    db::set_password(db, id, &form.new_pass, None)?;
+                    // Getting the mail proves the address works.
+                    db::set_email_verified(db, id, &email)?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
         })
+    })
+    .await?;
+    if !reset {
+        return Err(ApiError::InvalidToken);
+    }
+    no_content()
+}
+
+/// Fails unless `pass` is the current password of `user`.
+async fn confirm_password(pool: &DbPool, user: &User, pass: String) -> Result<(), ApiError> {
+    let username = user.username.clone();
+    run(pool, move |db| db::login(db, &username, &pass))
+        .await?
+        .map(|_| ())
+        .ok_or(ApiError::InvalidCredentials)
+}
+
+/// Replaces the caller's password after confirming the current one, and
+/// ends every other session of the account.
+#[post("/api/account_service/change_password")]
+pub async fn change_password(
+    pool: web::Data<DbPool>,
+    session: AuthenticatedSession,
+    form: web::Json<ChangePasswordForm>,
+) -> ApiResult {
+    let ChangePasswordForm { pass, new_pass } = form.into_inner();
+    if new_pass.is_empty() {
+        return Err(ApiError::InvalidInput(String::from(
+            "new_pass must not be empty",
+        )));
+    }
+    confirm_password(&pool, &session.user, pass).await?;
+    let (id, keep) = (session.user.id, session.id);
+    run(&pool, move |db| {
+        db::set_password(db, id, &new_pass, Some(&keep))
+    })
+    .await?;
+    no_content()
+}
+
+/// Replaces the caller's email after confirming their password, and mails
+/// a link to verify the new address.
+#[post("/api/account_service/change_email")]
+pub async fn change_email(
+    pool: web::Data<DbPool>,
+    mailer: web::Data<BoxedMailer>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<ChangeEmailForm>,
+) -> ApiResult {
+    let form = form.into_inner();
+    let email = form.email.trim().to_string();
+    if email.is_empty() {
+        return Err(ApiError::InvalidInput(String::from(
+            "email must not be empty",
+        )));
+    }
+    confirm_password(&pool, &user, form.pass).await?;
+    if email == user.email {
+        return no_content();
+    }
+    let id = user.id;
+    let user = run(&pool, move |db| db::set_email(db, id, &email)).await?;
+    if let Err(e) = send_verification(&pool, &mailer, &user).await {
+        log::error!(
+            "could not send verification mail to user {}: {}",
+            user.id,
+            e
+        );
+    }
+    no_content()
+}
+
+const MAX_BIO_LENGTH: usize = 2000;
+const MAX_LINKS: usize = 10;
+
+/// Profile links end up in `href`s on the blog, so only web URLs are taken.
+fn is_web_url(url: &str) -> bool {
+    (url.starts_with("https://") || url.starts_with("http://"))
+        && !url.contains(char::is_whitespace)
+}
+
+#[post("/api/account_service/edit_profile")]
+pub async fn edit_profile(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<EditProfileForm>,
+) -> ApiResult {
+    let form = form.into_inner();
+    let nickname = form.nickname.trim().to_string();
+    if nickname.is_empty() {
+        return Err(ApiError::InvalidInput(String::from(
+            "nickname must not be empty",
+        )));
+    }
+    let bio = form.bio.map_or(user.bio, |bio| bio.trim().to_string());
+    if bio.chars().count() > MAX_BIO_LENGTH {
+        return Err(ApiError::InvalidInput(format!(
+            "bio must be at most {} characters",
+            MAX_BIO_LENGTH
+        )));
+    }
+    let avatar_url = match form.avatar_url {
+        Some(url) if url.trim().is_empty() => None,
+        Some(url) => Some(url.trim().to_string()),
+        None => user.avatar_url,
+    };
+    let links = match form.links {
+        Some(links) => links
+            .iter()
+            .map(|link| link.trim().to_string())
+            .filter(|link| !link.is_empty())
+            .collect(),
+        None => user.links,
+    };
+    if links.len() > MAX_LINKS {
+        return Err(ApiError::InvalidInput(format!(
+            "at most {} links are allowed",
+            MAX_LINKS
+        )));
+    }
+    if let Some(url) = avatar_url.iter().chain(&links).find(|url| !is_web_url(url)) {
+        return Err(ApiError::InvalidInput(format!(
+            "{:?} is not an http(s) URL",
+            url
+        )));
+    }
+    let id = user.id;
+    run(&pool, move |db| {
+        let profile = db::ProfileEdit {
+            nickname: &nickname,
+            bio: &bio,
+            avatar_url: avatar_url.as_deref(),
+            links: &links,
+        };
+        db::set_profile(db, id, &profile)
+    })
+    .await?;
+    no_content()
 }
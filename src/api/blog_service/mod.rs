@@ -1,24 +1,40 @@
-pub mod errors;
 use chrono::prelude::*;
 
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::http::{header, HeaderValue};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
 
-use crate::api::account_service::*;
+use crate::api::errors::ApiError;
+use crate::api::{created, no_content, ok, page_size, paging, ApiResult};
 use crate::db;
-use crate::db::models::{AccountLevel, Post, PostHeader};
+use crate::db::models::{
+    ArchiveMonth, Capability, NewPost, Post, PostHeader, PostRevision, PostSort, PostStatus,
+    RevisionHeader, SearchHit, TagCount, User, Visibility,
+};
+use crate::middlewares::auth::{require, AuthenticatedUser, OptionalUser, PostKey};
+use crate::middlewares::markdown;
+use crate::middlewares::password::{self, Verification};
+use crate::middlewares::postgresql::{run, DbPool};
+use crate::middlewares::slug::slugify;
 use crate::CONFIG;
-use errors::*;
-// use hmac::{Hmac, NewMac};
-use jwt_simple::prelude::*;
-
-use std::cmp::max;
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct NewPostForm {
     pub title: String,
     pub body: String,
     pub tag: Vec<String>,
+    /// Defaults to `published`.
+    pub status: Option<PostStatus>,
+    /// Required when `status` is `scheduled`, in UTC.
+    pub publish_at: Option<NaiveDateTime>,
+    /// Made from the title when not given.
+    pub slug: Option<String>,
+    /// Defaults to `public`.
+    pub visibility: Option<Visibility>,
+    /// Required when `visibility` is `password`.
+    pub password: Option<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
@@ -27,71 +43,200 @@ pub struct EditPostForm {
     pub title: String,
     pub body: String,
     pub tag: Vec<String>,
+    /// The `version` of the post the edit is based on. Not needed when the
+    /// request carries an `If-Match` header instead.
+    pub version: Option<i32>,
+    /// Replaces the slug. When not given, the slug follows the title.
+    pub slug: Option<String>,
+    /// Keeps the current visibility when not given.
+    pub visibility: Option<Visibility>,
+    /// Replaces the password of a password-protected post.
+    pub password: Option<String>,
 }
 
+/// Which page of a listing to return.
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct PostsForm {
-    pub start: i64,
-    pub count: i64,
+    /// `next_cursor` or `prev_cursor` of a page loaded with the same `sort`.
+    /// The first page when not given.
+    pub cursor: Option<String>,
+    /// Defaults to `blog.page_size`, and is capped at `blog.max_page_size`.
+    pub count: Option<i64>,
+    #[serde(default)]
+    pub sort: PostSort,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct ViewPostForm {
     pub id: i64,
+    #[serde(default)]
+    pub format: BodyFormat,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
-pub struct DeletePostForm {
+pub struct PostBySlugForm {
+    #[serde(default)]
+    pub format: BodyFormat,
+}
+
+/// Which representations of the body `view_post` returns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyFormat {
+    Markdown,
+    Html,
+    #[default]
+    Both,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PostStatusForm {
     pub id: i64,
+    pub status: PostStatus,
+    /// Required when `status` is `scheduled`, in UTC.
+    pub publish_at: Option<NaiveDateTime>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct NewPostResponse {
-    pub error: BlogError,
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct DeletePostForm {
+    pub id: i64,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
-pub struct EditPostResponse {
-    pub error: BlogError,
+pub struct NewPostResponse {
+    pub id: i64,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct CountPostsResponse {
-    pub error: BlogError,
     pub count: i64,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct PublicPost {
+    pub id: i32,
+    pub slug: String,
     pub title: String,
-    pub body: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_markdown: Option<String>,
+    /// Sanitized HTML rendered from the Markdown body.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_html: Option<String>,
     pub author: i32,
     pub tags: Vec<String>,
+    pub status: PostStatus,
+    pub visibility: Visibility,
+    /// Opens an unlisted post; only shown to those who may edit it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share_token: Option<String>,
     pub created_at: NaiveDateTime,
     pub modified_at: NaiveDateTime,
+    pub published_at: Option<NaiveDateTime>,
+    /// Send this back when editing the post; also served as its `ETag`.
+    pub version: i32,
+}
+
+impl From<(Post, Vec<String>)> for PublicPost {
+    fn from((post, post_tags): (Post, Vec<String>)) -> Self {
+        let (body, body_html) = (post.body, post.body_html);
+        let body_html = body_html.unwrap_or_else(|| markdown::render(&body));
+        PublicPost {
+            id: post.id,
+            slug: post.slug,
+            title: post.title,
+            body_markdown: Some(body),
+            body_html: Some(body_html),
+            author: post.author,
+            tags: post_tags,
+            status: PostStatus::from_i32(post.status),
+            visibility: Visibility::from_i32(post.visibility),
+            share_token: post.share_token,
+            created_at: post.created_at,
+            modified_at: post.modified_at,
+            published_at: post.published_at,
+            version: post.revision,
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
-pub struct ViewPostResponse {
-    pub error: BlogError,
-    pub post: Option<PublicPost>,
+pub struct EditPostResponse {
+    pub version: i32,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
-pub struct DeletePostResponse {
-    pub error: BlogError,
+pub struct DraftPost {
+    pub id: i32,
+    pub title: String,
+    pub status: PostStatus,
+    pub created_at: NaiveDateTime,
+    pub modified_at: NaiveDateTime,
+    /// When a scheduled post goes live.
+    pub published_at: Option<NaiveDateTime>,
+}
+
+impl From<Post> for DraftPost {
+    fn from(post: Post) -> Self {
+        DraftPost {
+            id: post.id,
+            title: post.title,
+            status: PostStatus::from_i32(post.status),
+            created_at: post.created_at,
+            modified_at: post.modified_at,
+            published_at: post.published_at,
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
-pub struct RecentPostsResponse {
-    pub error: BlogError,
-    pub posts: Vec<i64>,
+pub struct DraftsResponse {
+    pub posts: Vec<DraftPost>,
 }
 
+/// A page of a listing, with the number of posts in the whole listing.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct PostsResponse {
-    pub error: BlogError,
+    pub count: i64,
     pub posts: Vec<PostHeader>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev_cursor: Option<String>,
+}
+
+impl From<db::PostPage> for PostsResponse {
+    fn from(page: db::PostPage) -> Self {
+        PostsResponse {
+            count: page.total,
+            posts: page.posts,
+            next_cursor: page.next.map(|cursor| cursor.encode()),
+            prev_cursor: page.prev.map(|cursor| cursor.encode()),
+        }
+    }
+}
+
+/// What anyone may see about a user who writes on the blog.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AuthorProfile {
+    pub id: i32,
+    pub nickname: String,
+    pub bio: String,
+    pub avatar_url: Option<String>,
+    pub links: Vec<String>,
+    /// Posts by the author the caller may list.
+    pub post_count: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ArchiveYear {
+    pub year: i32,
+    pub count: i64,
+    pub months: Vec<ArchiveMonth>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ArchiveResponse {
+    pub years: Vec<ArchiveYear>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -99,274 +244,1094 @@ pub struct RecentPostsRequest {
     pub count: i64,
 }
 
-#[post("/api/blog/new_post")]
-pub async fn new_post(parms: web::Json<AsRequest<NewPostForm>>) -> HttpResponse {
-    let config = CONFIG.clone();
-    let key = HS256Key::from_bytes(config.secret.secret.as_bytes());
-    let claims_wrapped = key.verify_token::<AccountToken>(&parms.token, None);
-    let json = if let Ok(claims) = claims_wrapped {
-        if let Ok(user) = db::find_user(claims.custom.pk) {
-            if user.permission == 1 {
-                if let Ok(_) = db::create_post(
-                    &parms.body.title,
-                    &parms.body.body,
-                    claims.custom.pk,
-                    parms.body.tag.iter().map(|s| s.as_str()).collect(),
-                    0,
-                ) {
-                    Some(NewPostResponse {
-                        error: BlogError::Nothing,
-                    })
-                } else {
-                    Some(NewPostResponse {
-                        error: BlogError::DatabaseError,
-                    })
-                }
-            } else {
-                Some(NewPostResponse {
-                    error: BlogError::AuthError,
-                })
-            }
-        } else {
-            Some(NewPostResponse {
-                error: BlogError::DatabaseError,
-            })
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct TagPostsForm {
+    pub tag: String,
+    pub cursor: Option<String>,
+    pub count: Option<i64>,
+    #[serde(default)]
+    pub sort: PostSort,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RenameTagForm {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TagsResponse {
+    pub tags: Vec<TagCount>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct SearchForm {
+    pub q: String,
+    pub start: i64,
+    pub count: i64,
+    pub tag: Option<String>,
+    pub author: Option<i32>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SearchResponse {
+    pub count: i64,
+    pub posts: Vec<SearchHit>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct HighlightCssForm {
+    pub theme: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HighlightThemesResponse {
+    pub default: String,
+    pub themes: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RevisionsForm {
+    pub id: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RevisionForm {
+    pub id: i64,
+    pub revision: i32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RevisionDiffForm {
+    pub id: i64,
+    pub from: i32,
+    pub to: i32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RevisionsResponse {
+    pub revisions: Vec<RevisionHeader>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffOp {
+    Equal,
+    Insert,
+    Delete,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub op: DiffOp,
+    pub text: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RevisionDiffResponse {
+    pub from: i32,
+    pub to: i32,
+    pub title: Vec<DiffLine>,
+    pub body: Vec<DiffLine>,
+    pub tags_added: Vec<String>,
+    pub tags_removed: Vec<String>,
+}
+
+/// Fails unless `user`, presenting `key`, may read `post`. Its author and
+/// those who may edit any post always can. For everyone else drafts,
+/// scheduled and private posts do not exist, and neither do unlisted posts
+/// unless opened with their share token.
+pub async fn check_readable(
+    post: &Post,
+    user: Option<&User>,
+    key: &PostKey,
+) -> Result<(), ApiError> {
+    if user.is_some_and(|user| user.id == post.author || user.can(Capability::EditAnyPost)) {
+        return Ok(());
+    }
+    let status = PostStatus::from_i32(post.status);
+    if status == PostStatus::Draft || status == PostStatus::Scheduled {
+        return Err(ApiError::PostNotFound);
+    }
+    match Visibility::from_i32(post.visibility) {
+        Visibility::Public => Ok(()),
+        Visibility::Unlisted
+            if post.share_token.is_some() && key.share_token == post.share_token =>
+        {
+            Ok(())
         }
-    } else {
-        Some(NewPostResponse {
-            error: BlogError::AuthError,
-        })
+        Visibility::Unlisted | Visibility::Private => Err(ApiError::PostNotFound),
+        Visibility::Members if user.is_some() => Ok(()),
+        Visibility::Members => Err(ApiError::Unauthorized),
+        Visibility::Password => check_post_password(post, key).await,
+    }
+}
+
+async fn check_post_password(post: &Post, key: &PostKey) -> Result<(), ApiError> {
+    let (pass, stored) = match (&key.password, &post.password_hash) {
+        (Some(pass), Some(stored)) => (pass.clone(), stored.clone()),
+        _ => return Err(ApiError::PostPasswordRequired),
     };
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(ResponseBlock {
-            status: json.is_some(),
-            body: json,
-        })
+    match web::block(move || password::verify(&pass, &stored)).await {
+        Ok(Verification::Valid) | Ok(Verification::NeedsRehash) => Ok(()),
+        Ok(Verification::Invalid) => Err(ApiError::PostPasswordRequired),
+        Err(_) => Err(ApiError::DatabaseError),
+    }
 }
 
-#[get("/api/blog/count_posts")]
-pub async fn count_posts() -> HttpResponse {
-    let json = if let Ok(cnt) = db::count_posts() {
-        Some(CountPostsResponse {
-            error: BlogError::Nothing,
-            count: cnt,
-        })
+/// Fails unless a post going to `visibility` will have a password: a new
+/// one, or the one it already has (`has_password`).
+fn check_post_password_set(
+    visibility: Visibility,
+    password: Option<&str>,
+    has_password: bool,
+) -> Result<(), ApiError> {
+    match password {
+        Some("") => Err(ApiError::InvalidInput(String::from(
+            "post password must not be empty",
+        ))),
+        None if visibility == Visibility::Password && !has_password => Err(ApiError::InvalidInput(
+            String::from("password is required for password-protected posts"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Fails unless `user` may change `post`: its author while still allowed to
+/// write posts, or anyone who may edit any post.
+fn check_editable(post: &Post, user: &User) -> Result<(), ApiError> {
+    if user.can(Capability::EditAnyPost)
+        || (post.author == user.id && user.can(Capability::WritePosts))
+    {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden)
+    }
+}
+
+/// Like `check_editable`, for deleting `post`.
+fn check_deletable(post: &Post, user: &User) -> Result<(), ApiError> {
+    if user.can(Capability::DeleteAnyPost)
+        || (post.author == user.id && user.can(Capability::WritePosts))
+    {
+        Ok(())
     } else {
-        None
+        Err(ApiError::Forbidden)
+    }
+}
+
+/// The `published_at` a post moving to `status` should get. `current` is
+/// the post as it is now, if it already exists.
+fn published_at(
+    status: PostStatus,
+    publish_at: Option<NaiveDateTime>,
+    current: Option<&Post>,
+) -> Result<Option<NaiveDateTime>, ApiError> {
+    let now = Utc::now().naive_utc();
+    match status {
+        PostStatus::Draft => Ok(None),
+        PostStatus::Scheduled => match publish_at {
+            Some(at) if at > now => Ok(Some(at)),
+            Some(_) => Err(ApiError::InvalidInput(String::from(
+                "publish_at must be in the future",
+            ))),
+            None => Err(ApiError::InvalidInput(String::from(
+                "publish_at is required for scheduled posts",
+            ))),
+        },
+        PostStatus::Published | PostStatus::Archived => {
+            let was_public = current.is_some_and(|post| {
+                let status = PostStatus::from_i32(post.status);
+                status == PostStatus::Published || status == PostStatus::Archived
+            });
+            Ok(current
+                .and_then(|post| post.published_at)
+                .filter(|_| was_public)
+                .or(Some(now)))
+        }
+    }
+}
+
+/// Fails if `slug` would clash with the current or a former slug of a post
+/// other than `post_id`.
+async fn check_slug(
+    pool: &DbPool,
+    slug: Option<&str>,
+    post_id: Option<i32>,
+) -> Result<(), ApiError> {
+    let slug = match slug {
+        Some(slug) => slugify(slug),
+        None => return Ok(()),
     };
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(ResponseBlock {
-            status: json.is_some(),
-            body: json,
-        })
+    match run(pool, move |db| db::slug_owner(db, &slug)).await? {
+        Some(owner) if Some(owner) != post_id => Err(ApiError::SlugAlreadyExists),
+        _ => Ok(()),
+    }
+}
+
+#[post("/api/blog/new_post")]
+pub async fn new_post(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<NewPostForm>,
+) -> ApiResult {
+    require(&user, Capability::WritePosts)?;
+    let form = form.into_inner();
+    let status = form.status.unwrap_or(PostStatus::Published);
+    let published_at = published_at(status, form.publish_at, None)?;
+    let visibility = form.visibility.unwrap_or(Visibility::Public);
+    check_post_password_set(visibility, form.password.as_deref(), false)?;
+    check_slug(&pool, form.slug.as_deref(), None).await?;
+    let post = run(&pool, move |db| {
+        db::create_post(
+            db,
+            &NewPost {
+                title: &form.title,
+                body: &form.body,
+                author: user.id,
+                status: status as i32,
+                published_at,
+                visibility: visibility as i32,
+            },
+            form.tag.iter().map(|s| s.as_str()).collect(),
+            form.slug.as_deref(),
+            form.password.as_deref(),
+        )
+    })
+    .await?;
+    created(NewPostResponse { id: post.id as i64 })
+}
+
+#[get("/api/blog/count_posts")]
+pub async fn count_posts(pool: web::Data<DbPool>, OptionalUser(user): OptionalUser) -> ApiResult {
+    let reader = reader(user.as_ref());
+    let count = run(&pool, move |db| db::count_posts(db, reader, None)).await?;
+    ok(CountPostsResponse { count })
 }
 
 #[post("/api/blog/view_post")]
-pub async fn view_post(parms: web::Json<AsRequest<ViewPostForm>>) -> HttpResponse {
-    let config = CONFIG.clone();
-    let key = HS256Key::from_bytes(config.secret.secret.as_bytes());
-    let claims_wrapped = key.verify_token::<AccountToken>(&parms.token, None);
-    let json = if let Ok(post) = db::by_post_id(parms.body.id as i32) {
-        if post.permission == 0 {
-            Some(ViewPostResponse {
-                error: BlogError::Nothing,
-                post: Some(PublicPost {
-                    title: post.title,
-                    body: post.body,
-                    author: post.author,
-                    tags: post.tags.split("|").map(|s| s.to_string()).collect(),
-                    created_at: post.created_at,
-                    modified_at: post.modified_at,
-                }),
-            })
-        } else {
-            if let Ok(claims) = claims_wrapped {
-                if let Ok(user) = db::find_user(claims.custom.pk) {
-                    if post.permission == user.permission {
-                        Some(ViewPostResponse {
-                            error: BlogError::Nothing,
-                            post: Some(PublicPost {
-                                title: post.title,
-                                body: post.body,
-                                author: post.author,
-                                tags: post.tags.split("|").map(|s| s.to_string()).collect(),
-                                created_at: post.created_at,
-                                modified_at: post.modified_at,
-                            }),
-                        })
-                    } else {
-                        Some(ViewPostResponse {
-                            error: BlogError::DatabaseError,
-                            post: None,
-                        })
-                    }
-                } else {
-                    Some(ViewPostResponse {
-                        error: BlogError::PermissionError,
-                        post: None,
-                    })
-                }
-            } else {
-                Some(ViewPostResponse {
-                    error: BlogError::AuthError,
-                    post: None,
-                })
-            }
+pub async fn view_post(
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    key: PostKey,
+    parms: web::Json<ViewPostForm>,
+) -> ApiResult {
+    let id = parms.id as i32;
+    let (post, post_tags) = run(&pool, move |db| {
+        match db::by_post_id(db, id).optional()? {
+            Some(post) => Ok(Some((post, db::tags_of(db, id)?))),
+            None => Ok(None),
         }
-    } else {
-        Some(ViewPostResponse {
-            error: BlogError::DatabaseError,
-            post: None,
-        })
-    };
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(ResponseBlock {
-            status: json.is_some(),
-            body: json,
-        })
+    })
+    .await?
+    .ok_or(ApiError::PostNotFound)?;
+    check_readable(&post, user.as_ref(), &key).await?;
+    present(post, post_tags, parms.format, user.as_ref())
 }
 
-#[post("/api/blog/delete_post")]
-pub async fn delete_post(parms: web::Json<AsRequest<DeletePostForm>>) -> HttpResponse {
-    let config = CONFIG.clone();
-    let key = HS256Key::from_bytes(config.secret.secret.as_bytes());
-    let claims_wrapped = key.verify_token::<AccountToken>(&parms.token, None);
-    let body = if let Ok(claims) = claims_wrapped {
-        if let Ok(post) = db::by_post_id(parms.body.id as i32) {
-            if post.author == claims.custom.pk {
-                if let Ok(_) = db::delete_post(parms.body.id as i32) {
-                    Some(DeletePostResponse {
-                        error: BlogError::Nothing,
-                    })
-                } else {
-                    Some(DeletePostResponse {
-                        error: BlogError::DatabaseError,
-                    })
-                }
-            } else {
-                Some(DeletePostResponse {
-                    error: BlogError::AuthError,
-                })
-            }
-        } else {
-            Some(DeletePostResponse {
-                error: BlogError::DatabaseError,
-            })
+fn present(
+    post: Post,
+    post_tags: Vec<String>,
+    format: BodyFormat,
+    user: Option<&User>,
+) -> ApiResult {
+    let version = post.revision;
+    let editor = user.is_some_and(|user| check_editable(&post, user).is_ok());
+    let mut public = PublicPost::from((post, post_tags));
+    if !editor {
+        public.share_token = None;
+    }
+    match format {
+        BodyFormat::Markdown => public.body_html = None,
+        BodyFormat::Html => public.body_markdown = None,
+        BodyFormat::Both => {}
+    }
+    with_etag(ok(public), version)
+}
+
+/// A post by its slug. Former slugs of a post answer with a permanent
+/// redirect to the current one.
+#[get("/api/blog/posts/by-slug/{slug}")]
+pub async fn post_by_slug(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    key: PostKey,
+    web::Path(slug): web::Path<String>,
+    web::Query(parms): web::Query<PostBySlugForm>,
+) -> ApiResult {
+    let (post, post_tags, moved) = run(&pool, move |db| {
+        let (post, moved) = match db::by_slug(db, &slug).optional()? {
+            Some(post) => (post, false),
+            None => match db::by_former_slug(db, &slug).optional()? {
+                Some(post) => (post, true),
+                None => return Ok(None),
+            },
+        };
+        let post_tags = db::tags_of(db, post.id)?;
+        Ok(Some((post, post_tags, moved)))
+    })
+    .await?
+    .ok_or(ApiError::PostNotFound)?;
+    check_readable(&post, user.as_ref(), &key).await?;
+    if moved {
+        let mut location = format!("/api/blog/posts/by-slug/{}", post.slug);
+        if !req.query_string().is_empty() {
+            location.push('?');
+            location.push_str(req.query_string());
         }
-    } else {
-        Some(DeletePostResponse {
-            error: BlogError::AuthError,
-        })
+        return Ok(HttpResponse::MovedPermanently()
+            .header(header::LOCATION, location)
+            .finish());
+    }
+    present(post, post_tags, parms.format, user.as_ref())
+}
+
+/// An unlisted post by the token from its share link.
+#[get("/api/blog/posts/shared/{token}")]
+pub async fn shared_post(
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    web::Path(token): web::Path<String>,
+    web::Query(parms): web::Query<PostBySlugForm>,
+) -> ApiResult {
+    let key = PostKey {
+        password: None,
+        share_token: Some(token.clone()),
     };
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(ResponseBlock {
-            status: body.is_some(),
-            body,
-        })
+    let (post, post_tags) = run(&pool, move |db| {
+        match db::by_share_token(db, &token).optional()? {
+            Some(post) => {
+                let post_tags = db::tags_of(db, post.id)?;
+                Ok(Some((post, post_tags)))
+            }
+            None => Ok(None),
+        }
+    })
+    .await?
+    .ok_or(ApiError::PostNotFound)?;
+    check_readable(&post, user.as_ref(), &key).await?;
+    present(post, post_tags, parms.format, user.as_ref())
+}
+
+/// Moves a post to another stage of its lifecycle.
+#[post("/api/blog/set_post_status")]
+pub async fn set_post_status(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<PostStatusForm>,
+) -> ApiResult {
+    let form = form.into_inner();
+    let id = form.id as i32;
+    let post = run(&pool, move |db| db::by_post_id(db, id).optional())
+        .await?
+        .ok_or(ApiError::PostNotFound)?;
+    check_editable(&post, &user)?;
+    let status = form.status;
+    let published_at = published_at(status, form.publish_at, Some(&post))?;
+    run(&pool, move |db| {
+        db::set_post_status(db, id, status, published_at)
+    })
+    .await?;
+    no_content()
+}
+
+/// The caller's drafts and scheduled posts.
+#[get("/api/blog/drafts")]
+pub async fn drafts(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> ApiResult {
+    let list = run(&pool, move |db| db::unpublished_posts_of(db, user.id)).await?;
+    ok(DraftsResponse {
+        posts: list.into_iter().map(DraftPost::from).collect(),
+    })
+}
+
+#[post("/api/blog/delete_post")]
+pub async fn delete_post(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    parms: web::Json<DeletePostForm>,
+) -> ApiResult {
+    let id = parms.id as i32;
+    let post = run(&pool, move |db| db::by_post_id(db, id).optional())
+        .await?
+        .ok_or(ApiError::PostNotFound)?;
+    check_deletable(&post, &user)?;
+    run(&pool, move |db| db::delete_post(db, id)).await?;
+    no_content()
 }
 
 #[get("/api/blog/recent_posts")]
-pub async fn recent_posts(web::Query(parms): web::Query<RecentPostsRequest>) -> HttpResponse {
-    let body = if let Ok(cnt) = db::count_posts() {
-        if let Ok(list) = db::posts_by(0, parms.count) {
-            Some(RecentPostsResponse {
-                error: BlogError::Nothing,
-                posts: list.iter().map(|&s| s as i64).collect(),
-            })
-        } else {
-            Some(RecentPostsResponse {
-                error: BlogError::DatabaseError,
-                posts: vec![],
-            })
-        }
-    } else {
-        Some(RecentPostsResponse {
-            error: BlogError::DatabaseError,
-            posts: vec![],
-        })
+pub async fn recent_posts(
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    web::Query(parms): web::Query<RecentPostsRequest>,
+) -> ApiResult {
+    let page = PostsForm {
+        cursor: None,
+        count: Some(parms.count),
+        sort: PostSort::Modified,
     };
+    list_posts(&pool, reader(user.as_ref()), ListingFilter::default(), page).await
+}
 
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(ResponseBlock {
-            status: body.is_some(),
-            body,
-        })
+fn etag(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+fn with_etag(res: ApiResult, version: i32) -> ApiResult {
+    let mut res = res?;
+    if let Ok(value) = HeaderValue::from_str(&etag(version)) {
+        res.headers_mut().insert(header::ETAG, value);
+    }
+    Ok(res)
 }
 
+/// The current copy of a post, sent along with a rejected edit.
+async fn current_copy(pool: &DbPool, id: i32) -> Result<serde_json::Value, ApiError> {
+    let current = run(pool, move |db| match db::by_post_id(db, id).optional()? {
+        Some(post) => Ok(Some((post, db::tags_of(db, id)?))),
+        None => Ok(None),
+    })
+    .await?
+    .ok_or(ApiError::PostNotFound)?;
+    serde_json::to_value(PublicPost::from(current)).map_err(|_| ApiError::DatabaseError)
+}
+
+/// Saves an edit, refusing it with the current copy of the post unless it
+/// is based on the latest version. The version comes from `If-Match`, which
+/// is answered with 412 when stale, or from the form's `version`, which is
+/// answered with 409.
 #[post("/api/blog/edit_post")]
-pub async fn edit_post(parms: web::Json<AsRequest<EditPostForm>>) -> HttpResponse {
-    let config = CONFIG.clone();
-    let key = HS256Key::from_bytes(config.secret.secret.as_bytes());
-    let claims_wrapped = key.verify_token::<AccountToken>(&parms.token, None);
-    let json = if let Ok(claims) = claims_wrapped {
-        if let Ok(_) = db::find_user(claims.custom.pk as i32) {
-            if let Ok(post) = db::by_post_id(parms.body.pk as i32) {
-                if post.author == claims.custom.pk as i32 {
-                    if let Ok(_) = db::edit_post(
-                        parms.body.pk as i32,
-                        &parms.body.title,
-                        &parms.body.body,
-                        &parms.body.tag.join("|"),
-                    ) {
-                        Some(EditPostResponse {
-                            error: BlogError::Nothing,
-                        })
-                    } else {
-                        Some(EditPostResponse {
-                            error: BlogError::DatabaseError,
-                        })
-                    }
-                } else {
-                    Some(EditPostResponse {
-                        error: BlogError::AuthError,
-                    })
-                }
-            } else {
-                Some(EditPostResponse {
-                    error: BlogError::DatabaseError,
-                })
+pub async fn edit_post(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<EditPostForm>,
+) -> ApiResult {
+    let form = form.into_inner();
+    let id = form.pk as i32;
+    let post = run(&pool, move |db| db::by_post_id(db, id).optional())
+        .await?
+        .ok_or(ApiError::PostNotFound)?;
+    check_editable(&post, &user)?;
+    let if_match = req
+        .headers()
+        .get(header::IF_MATCH)
+        .map(|value| value.to_str().unwrap_or_default().to_string());
+    let expected = match (&if_match, form.version) {
+        (Some(etags), _) => {
+            let current = etag(post.revision);
+            let fresh = etags
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == current || tag == "*");
+            if !fresh {
+                return Err(ApiError::PreconditionFailed(current_copy(&pool, id).await?));
             }
-        } else {
-            Some(EditPostResponse {
-                error: BlogError::DatabaseError,
-            })
+            post.revision
         }
-    } else {
-        Some(EditPostResponse {
-            error: BlogError::AuthError,
-        })
+        (None, Some(version)) => version,
+        (None, None) => return Err(ApiError::VersionRequired),
     };
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(ResponseBlock {
-            status: json.is_some(),
-            body: json,
-        })
+    check_post_password_set(
+        form.visibility
+            .unwrap_or_else(|| Visibility::from_i32(post.visibility)),
+        form.password.as_deref(),
+        post.password_hash.is_some(),
+    )?;
+    check_slug(&pool, form.slug.as_deref(), Some(id)).await?;
+    let saved = run(&pool, move |db| {
+        let edit = db::PostEdit {
+            title: &form.title,
+            body: &form.body,
+            tags: form.tag.iter().map(|s| s.as_str()).collect(),
+            slug: form.slug.as_deref(),
+            visibility: form.visibility,
+            password: form.password.as_deref(),
+        };
+        db::edit_post(db, id, &edit, user.id, expected)
+    })
+    .await?;
+    match saved {
+        Some(post) => with_etag(
+            ok(EditPostResponse {
+                version: post.revision,
+            }),
+            post.revision,
+        ),
+        None if if_match.is_some() => {
+            Err(ApiError::PreconditionFailed(current_copy(&pool, id).await?))
+        }
+        None => Err(ApiError::VersionConflict(current_copy(&pool, id).await?)),
+    }
+}
+
+/// Narrows a listing down; `None` means "any".
+#[derive(Default)]
+struct ListingFilter {
+    tag: Option<String>,
+    author: Option<i32>,
+    /// Days in `blog.timezone` the posts were published in, the end
+    /// excluded.
+    published: Option<(NaiveDate, NaiveDate)>,
+}
+
+/// What listings show `user`, matching the posts `check_readable` lets them
+/// open.
+fn reader(user: Option<&User>) -> db::Reader {
+    db::Reader {
+        id: user.map(|user| user.id),
+        editor: user.is_some_and(|user| user.can(Capability::EditAnyPost)),
+    }
+}
+
+/// A page of the published posts a reader may list that match `filter`.
+async fn list_posts(
+    pool: &DbPool,
+    reader: db::Reader,
+    filter: ListingFilter,
+    page: PostsForm,
+) -> ApiResult {
+    let cursor = match &page.cursor {
+        Some(cursor) => match db::Cursor::decode(cursor) {
+            Some(cursor) if cursor.sort == page.sort => Some(cursor),
+            _ => {
+                return Err(ApiError::InvalidInput(String::from(
+                    "cursor is invalid or belongs to another sort",
+                )))
+            }
+        },
+        None => None,
+    };
+    let count = page_size(page.count.unwrap_or(CONFIG.blog.page_size))?;
+    let page = run(pool, move |db| {
+        db::list_posts(
+            db,
+            &db::PostListing {
+                reader,
+                tag: filter.tag.as_deref(),
+                author: filter.author,
+                from: filter.published.map(|(from, _)| from),
+                to: filter.published.map(|(_, to)| to),
+                sort: page.sort,
+                cursor: cursor.as_ref(),
+                count,
+            },
+        )
+    })
+    .await?;
+    ok(PostsResponse::from(page))
 }
 
 #[get("/api/blog/posts")]
-pub async fn posts(web::Query(parms): web::Query<PostsForm>) -> HttpResponse {
-    let body = if let Ok(list) = db::post_header_by(parms.start, parms.count) {
-        Some(PostsResponse {
-            error: BlogError::Nothing,
-            posts: list,
-        })
-    } else {
-        Some(PostsResponse {
-            error: BlogError::DatabaseError,
-            posts: vec![],
-        })
+pub async fn posts(
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    web::Query(parms): web::Query<PostsForm>,
+) -> ApiResult {
+    list_posts(
+        &pool,
+        reader(user.as_ref()),
+        ListingFilter::default(),
+        parms,
+    )
+    .await
+}
+
+#[get("/api/blog/tags")]
+pub async fn tags(pool: web::Data<DbPool>, OptionalUser(user): OptionalUser) -> ApiResult {
+    let reader = reader(user.as_ref());
+    let list = run(&pool, move |db| db::tag_counts(db, reader)).await?;
+    ok(TagsResponse { tags: list })
+}
+
+#[get("/api/blog/tag_posts")]
+pub async fn tag_posts(
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    web::Query(parms): web::Query<TagPostsForm>,
+) -> ApiResult {
+    let page = PostsForm {
+        cursor: parms.cursor,
+        count: parms.count,
+        sort: parms.sort,
     };
+    let filter = ListingFilter {
+        tag: Some(parms.tag),
+        ..ListingFilter::default()
+    };
+    list_posts(&pool, reader(user.as_ref()), filter, page).await
+}
 
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(ResponseBlock {
-            status: body.is_some(),
-            body,
+#[get("/api/blog/authors/{id}")]
+pub async fn author_profile(
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    web::Path(id): web::Path<i32>,
+) -> ApiResult {
+    let reader = reader(user.as_ref());
+    let (author, post_count) = run(&pool, move |db| match db::find_user(db, id).optional()? {
+        Some(author) => Ok(Some((author, db::count_posts(db, reader, Some(id))?))),
+        None => Ok(None),
+    })
+    .await?
+    .ok_or(ApiError::UserNotFound)?;
+    ok(AuthorProfile {
+        id: author.id,
+        nickname: author.nickname,
+        bio: author.bio,
+        avatar_url: author.avatar_url,
+        links: author.links,
+        post_count,
+    })
+}
+
+#[get("/api/blog/authors/{id}/posts")]
+pub async fn author_posts(
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    web::Path(id): web::Path<i32>,
+    web::Query(parms): web::Query<PostsForm>,
+) -> ApiResult {
+    run(&pool, move |db| db::find_user(db, id).optional())
+        .await?
+        .ok_or(ApiError::UserNotFound)?;
+    let filter = ListingFilter {
+        author: Some(id),
+        ..ListingFilter::default()
+    };
+    list_posts(&pool, reader(user.as_ref()), filter, parms).await
+}
+
+/// Post counts by year and month, newest first.
+#[get("/api/blog/archive")]
+pub async fn archive(pool: web::Data<DbPool>, OptionalUser(user): OptionalUser) -> ApiResult {
+    let reader = reader(user.as_ref());
+    let months = run(&pool, move |db| db::archive_months(db, reader)).await?;
+    let mut years: Vec<ArchiveYear> = Vec::new();
+    for month in months {
+        match years.last_mut() {
+            Some(year) if year.year == month.year => {
+                year.count += month.count;
+                year.months.push(month);
+            }
+            _ => years.push(ArchiveYear {
+                year: month.year,
+                count: month.count,
+                months: vec![month],
+            }),
+        }
+    }
+    ok(ArchiveResponse { years })
+}
+
+/// The first day of `year`, or of `month` in it, and the first day after.
+fn archive_period(year: i32, month: Option<u32>) -> Result<(NaiveDate, NaiveDate), ApiError> {
+    let invalid = || ApiError::InvalidInput(String::from("no such year or month"));
+    let (from, to) = match month {
+        Some(month) if !(1..=12).contains(&month) => return Err(invalid()),
+        Some(12) => (
+            NaiveDate::from_ymd_opt(year, 12, 1),
+            NaiveDate::from_ymd_opt(year.checked_add(1).ok_or_else(invalid)?, 1, 1),
+        ),
+        Some(month) => (
+            NaiveDate::from_ymd_opt(year, month, 1),
+            NaiveDate::from_ymd_opt(year, month + 1, 1),
+        ),
+        None => (
+            NaiveDate::from_ymd_opt(year, 1, 1),
+            NaiveDate::from_ymd_opt(year.checked_add(1).ok_or_else(invalid)?, 1, 1),
+        ),
+    };
+    Ok((from.ok_or_else(invalid)?, to.ok_or_else(invalid)?))
+}
+
+#[get("/api/blog/archive/{year}")]
+pub async fn archive_year(
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    web::Path(year): web::Path<i32>,
+    web::Query(parms): web::Query<PostsForm>,
+) -> ApiResult {
+    let filter = ListingFilter {
+        published: Some(archive_period(year, None)?),
+        ..ListingFilter::default()
+    };
+    list_posts(&pool, reader(user.as_ref()), filter, parms).await
+}
+
+#[get("/api/blog/archive/{year}/{month}")]
+pub async fn archive_month(
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    web::Path((year, month)): web::Path<(i32, u32)>,
+    web::Query(parms): web::Query<PostsForm>,
+) -> ApiResult {
+    let filter = ListingFilter {
+        published: Some(archive_period(year, Some(month))?),
+        ..ListingFilter::default()
+    };
+    list_posts(&pool, reader(user.as_ref()), filter, parms).await
+}
+
+/// Renames a tag, merging it into `to` when a tag with that name already exists.
+#[post("/api/blog/rename_tag")]
+pub async fn rename_tag(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<RenameTagForm>,
+) -> ApiResult {
+    require(&user, Capability::ManageTags)?;
+    let form = form.into_inner();
+    if form.to.trim().is_empty() {
+        return Err(ApiError::InvalidInput(String::from(
+            "tag name must not be empty",
+        )));
+    }
+    if !run(&pool, move |db| {
+        db::rename_tag(db, form.from.trim(), form.to.trim())
+    })
+    .await?
+    {
+        return Err(ApiError::TagNotFound);
+    }
+    no_content()
+}
+
+/// Full-text search over titles, tags and bodies, best matches first.
+#[get("/api/blog/search")]
+pub async fn search(
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    web::Query(parms): web::Query<SearchForm>,
+) -> ApiResult {
+    if parms.q.trim().is_empty() {
+        return Err(ApiError::InvalidInput(String::from(
+            "search query must not be empty",
+        )));
+    }
+    let (start, count) = paging(parms.start, parms.count)?;
+    let reader = reader(user.as_ref());
+    let q = parms.q;
+    let filter = db::SearchFilter {
+        tag: parms.tag,
+        author: parms.author,
+        from: parms.from,
+        to: parms.to,
+    };
+    let (count, hits) = run(&pool, move |db| {
+        db::search_posts(db, &q, reader, &filter, start, count)
+    })
+    .await?;
+    ok(SearchResponse { count, posts: hits })
+}
+
+/// Loads post `id`, failing unless `user` may edit it.
+async fn editable_post(pool: &DbPool, id: i32, user: &User) -> Result<Post, ApiError> {
+    let post = run(pool, move |db| db::by_post_id(db, id).optional())
+        .await?
+        .ok_or(ApiError::PostNotFound)?;
+    check_editable(&post, user)?;
+    Ok(post)
+}
+
+async fn load_revision(pool: &DbPool, id: i32, revision: i32) -> Result<PostRevision, ApiError> {
+    run(pool, move |db| {
+        db::find_revision(db, id, revision).optional()
+    })
+    .await?
+    .ok_or(ApiError::RevisionNotFound)
+}
+
+fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    // Terminate both sides so an unchanged last line still compares equal
+    // when a line is appended after it.
+    let (old, new) = (format!("{}\n", old), format!("{}\n", new));
+    TextDiff::from_lines(&old, &new)
+        .iter_all_changes()
+        .map(|change| DiffLine {
+            op: match change.tag() {
+                ChangeTag::Equal => DiffOp::Equal,
+                ChangeTag::Insert => DiffOp::Insert,
+                ChangeTag::Delete => DiffOp::Delete,
+            },
+            text: change.value().trim_end_matches('\n').to_string(),
         })
+        .collect()
+}
+
+/// Every saved revision of a post, newest first.
+#[get("/api/blog/revisions")]
+pub async fn revisions(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    web::Query(parms): web::Query<RevisionsForm>,
+) -> ApiResult {
+    let id = parms.id as i32;
+    editable_post(&pool, id, &user).await?;
+    let list = run(&pool, move |db| db::revisions_of(db, id)).await?;
+    ok(RevisionsResponse { revisions: list })
+}
+
+#[get("/api/blog/revision")]
+pub async fn view_revision(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    web::Query(parms): web::Query<RevisionForm>,
+) -> ApiResult {
+    let id = parms.id as i32;
+    editable_post(&pool, id, &user).await?;
+    ok(load_revision(&pool, id, parms.revision).await?)
+}
+
+/// Line-level changes going from revision `from` to revision `to`.
+#[get("/api/blog/revision_diff")]
+pub async fn revision_diff(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    web::Query(parms): web::Query<RevisionDiffForm>,
+) -> ApiResult {
+    let id = parms.id as i32;
+    editable_post(&pool, id, &user).await?;
+    let old = load_revision(&pool, id, parms.from).await?;
+    let new = load_revision(&pool, id, parms.to).await?;
+    ok(RevisionDiffResponse {
+        from: old.revision,
+        to: new.revision,
+        title: diff_lines(&old.title, &new.title),
+        body: diff_lines(&old.body, &new.body),
+        tags_added: new
+            .tags
+            .iter()
+            .filter(|tag| !old.tags.contains(tag))
+            .cloned()
+            .collect(),
+        tags_removed: old
+            .tags
+            .iter()
+            .filter(|tag| !new.tags.contains(tag))
+            .cloned()
+            .collect(),
+    })
+}
+
+/// Makes an old revision the current content of the post. This is saved as
+/// a new revision, so the history in between is kept.
+#[post("/api/blog/restore_revision")]
+pub async fn restore_revision(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<RevisionForm>,
+) -> ApiResult {
+    let id = form.id as i32;
+    let post = editable_post(&pool, id, &user).await?;
+    let old = load_revision(&pool, id, form.revision).await?;
+    let saved = run(&pool, move |db| {
+        let edit = db::PostEdit {
+            title: &old.title,
+            body: &old.body,
+            tags: old.tags.iter().map(|s| s.as_str()).collect(),
+            slug: None,
+            visibility: None,
+            password: None,
+        };
+        db::edit_post(db, id, &edit, user.id, post.revision)
+    })
+    .await?;
+    match saved {
+        Some(post) => with_etag(
+            ok(EditPostResponse {
+                version: post.revision,
+            }),
+            post.revision,
+        ),
+        None => Err(ApiError::VersionConflict(current_copy(&pool, id).await?)),
+    }
+}
+
+/// Stylesheet for highlighted code blocks in `body_html`, in the configured
+/// theme unless another is asked for.
+#[get("/api/blog/highlight.css")]
+pub async fn highlight_css(web::Query(parms): web::Query<HighlightCssForm>) -> ApiResult {
+    let theme = parms
+        .theme
+        .unwrap_or_else(|| CONFIG.blog.highlight_theme.clone());
+    let css = markdown::theme_css(&theme)
+        .ok_or_else(|| ApiError::InvalidInput(format!("unknown theme {:?}", theme)))?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/css; charset=utf-8")
+        .header(header::CACHE_CONTROL, "public, max-age=86400")
+        .body(css))
+}
+
+#[get("/api/blog/highlight_themes")]
+pub async fn highlight_themes() -> ApiResult {
+    ok(HighlightThemesResponse {
+        default: CONFIG.blog.highlight_theme.clone(),
+        themes: markdown::theme_names(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn post(status: PostStatus, published_at: Option<NaiveDateTime>) -> Post {
+        let now = Utc::now().naive_utc();
+        Post {
+            id: 1,
+            title: String::from("title"),
+            body: String::from("body"),
+            author: 1,
+            created_at: now,
+            modified_at: now,
+            comments_closed: false,
+            status: status as i32,
+            published_at,
+            revision: 1,
+            body_html: None,
+            slug: String::from("title"),
+            visibility: Visibility::Public as i32,
+            password_hash: None,
+            share_token: None,
+        }
+    }
+
+    #[test]
+    fn drafts_are_unpublished() {
+        let published = post(
+            PostStatus::Published,
+            Some(day(2021, 1, 1).and_hms_opt(0, 0, 0).unwrap()),
+        );
+        assert_eq!(published_at(PostStatus::Draft, None, None).unwrap(), None);
+        assert_eq!(
+            published_at(PostStatus::Draft, None, Some(&published)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn scheduling_needs_a_future_date() {
+        let hour = chrono::Duration::hours(1);
+        let future = Utc::now().naive_utc() + hour;
+        let past = Utc::now().naive_utc() - hour;
+        assert_eq!(
+            published_at(PostStatus::Scheduled, Some(future), None).unwrap(),
+            Some(future)
+        );
+        assert!(published_at(PostStatus::Scheduled, Some(past), None).is_err());
+        assert!(published_at(PostStatus::Scheduled, None, None).is_err());
+    }
+
+    #[test]
+    fn publishing_keeps_the_first_publication_date() {
+        let before = Utc::now().naive_utc();
+        let fresh = published_at(PostStatus::Published, None, None)
+            .unwrap()
+            .unwrap();
+        assert!(fresh >= before);
+
+        let first = day(2021, 1, 1).and_hms_opt(0, 0, 0).unwrap();
+        let published = post(PostStatus::Published, Some(first));
+        assert_eq!(
+            published_at(PostStatus::Published, None, Some(&published)).unwrap(),
+            Some(first)
+        );
+        assert_eq!(
+            published_at(PostStatus::Archived, None, Some(&published)).unwrap(),
+            Some(first)
+        );
+
+        // A scheduled date that was never reached is not a publication date.
+        let later = Utc::now().naive_utc() + chrono::Duration::days(1);
+        let scheduled = post(PostStatus::Scheduled, Some(later));
+        let now = published_at(PostStatus::Published, None, Some(&scheduled))
+            .unwrap()
+            .unwrap();
+        assert!(now >= before && now < later);
+    }
+
+    fn ops(old: &str, new: &str) -> Vec<(DiffOp, String)> {
+        diff_lines(old, new)
+            .into_iter()
+            .map(|line| (line.op, line.text))
+            .collect()
+    }
+
+    #[test]
+    fn diff_of_equal_texts_is_all_equal() {
+        assert_eq!(
+            ops("a\nb", "a\nb"),
+            vec![(DiffOp::Equal, "a".into()), (DiffOp::Equal, "b".into())]
+        );
+    }
+
+    #[test]
+    fn diff_keeps_the_last_line_when_appending() {
+        assert_eq!(
+            ops("a\nb", "a\nb\nc"),
+            vec![
+                (DiffOp::Equal, "a".into()),
+                (DiffOp::Equal, "b".into()),
+                (DiffOp::Insert, "c".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_replaces_changed_lines() {
+        assert_eq!(
+            ops("a\nb\nc", "a\nB\nc"),
+            vec![
+                (DiffOp::Equal, "a".into()),
+                (DiffOp::Delete, "b".into()),
+                (DiffOp::Insert, "B".into()),
+                (DiffOp::Equal, "c".into()),
+            ]
+        );
+        assert_eq!(
+            ops("a\nb", "b"),
+            vec![(DiffOp::Delete, "a".into()), (DiffOp::Equal, "b".into())]
+        );
+    }
+
+    #[test]
+    fn archive_period_spans_a_year_or_month() {
+        assert_eq!(
+            archive_period(2021, None).unwrap(),
+            (day(2021, 1, 1), day(2022, 1, 1))
+        );
+        assert_eq!(
+            archive_period(2021, Some(2)).unwrap(),
+            (day(2021, 2, 1), day(2021, 3, 1))
+        );
+        assert_eq!(
+            archive_period(2021, Some(12)).unwrap(),
+            (day(2021, 12, 1), day(2022, 1, 1))
+        );
+    }
+
+    #[test]
+    fn archive_period_rejects_months_out_of_range() {
+        assert!(archive_period(2021, Some(0)).is_err());
+        assert!(archive_period(2021, Some(13)).is_err());
+        assert!(archive_period(2021, Some(u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn archive_period_rejects_years_out_of_range() {
+        assert!(archive_period(i32::MAX, None).is_err());
+        assert!(archive_period(i32::MAX, Some(12)).is_err());
+        assert!(archive_period(i32::MIN, None).is_err());
+        assert!(archive_period(i32::MIN, Some(1)).is_err());
+    }
 }
@@ -0,0 +1,455 @@
+use std::collections::HashMap;
+
+use actix_web::{get, post, web};
+use chrono::prelude::*;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::api::blog_service::check_readable;
+use crate::api::errors::ApiError;
+use crate::api::{created, no_content, ok, paging, ApiResult};
+use crate::db;
+use crate::db::models::{Capability, Comment, CommentStatus, NewComment, User};
+use crate::middlewares::auth::{require, AuthenticatedUser, OptionalUser, PostKey};
+use crate::middlewares::postgresql::{run, DbPool};
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct CommentsForm {
+    pub post_id: i32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct NewCommentForm {
+    pub post_id: i32,
+    pub parent_id: Option<i32>,
+    pub body: String,
+    /// Required when commenting without a token, ignored otherwise.
+    pub author_name: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct EditCommentForm {
+    pub id: i32,
+    pub body: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct DeleteCommentForm {
+    pub id: i32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct CommentQueueForm {
+    pub start: i64,
+    /// Capped at `blog.max_page_size`.
+    pub count: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ModerateCommentsForm {
+    pub ids: Vec<i32>,
+    pub status: CommentStatus,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct CloseCommentsForm {
+    pub post_id: i32,
+    pub closed: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PublicComment {
+    pub id: i32,
+    pub post_id: i32,
+    pub parent_id: Option<i32>,
+    pub author: Option<i32>,
+    /// The author's nickname, or the name given by an anonymous commenter.
+    pub author_name: Option<String>,
+    pub body: String,
+    pub status: CommentStatus,
+    pub created_at: NaiveDateTime,
+    pub modified_at: NaiveDateTime,
+    pub replies: Vec<PublicComment>,
+}
+
+impl From<(Comment, Option<String>)> for PublicComment {
+    fn from((comment, nickname): (Comment, Option<String>)) -> Self {
+        let status = CommentStatus::from_i32(comment.status);
+        let deleted = status == CommentStatus::Deleted;
+        PublicComment {
+            id: comment.id,
+            post_id: comment.post_id,
+            parent_id: comment.parent_id,
+            author: comment.author.filter(|_| !deleted),
+            author_name: nickname.or(comment.author_name).filter(|_| !deleted),
+            body: if deleted { String::new() } else { comment.body },
+            status,
+            created_at: comment.created_at,
+            modified_at: comment.modified_at,
+            replies: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CommentsResponse {
+    pub comments_closed: bool,
+    pub comments: Vec<PublicComment>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NewCommentResponse {
+    pub id: i32,
+    pub status: CommentStatus,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CommentQueueResponse {
+    pub count: i64,
+    pub comments: Vec<PublicComment>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ModerateCommentsResponse {
+    pub count: i64,
+}
+
+fn is_moderator(user: &User) -> bool {
+    user.can(Capability::ModerateComments)
+}
+
+/// Nests `list` into reply trees. Replies to comments missing from `list`
+/// are dropped, and deleted comments are kept only as placeholders for
+/// their remaining replies.
+fn thread(list: Vec<(Comment, Option<String>)>) -> Vec<PublicComment> {
+    fn collect(
+        parent: Option<i32>,
+        children: &mut HashMap<Option<i32>, Vec<PublicComment>>,
+    ) -> Vec<PublicComment> {
+        children
+            .remove(&parent)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|mut comment| {
+                comment.replies = collect(Some(comment.id), children);
+                if comment.status == CommentStatus::Deleted && comment.replies.is_empty() {
+                    None
+                } else {
+                    Some(comment)
+                }
+            })
+            .collect()
+    }
+
+    let mut children: HashMap<Option<i32>, Vec<PublicComment>> = HashMap::new();
+    for row in list {
+        let comment = PublicComment::from(row);
+        children.entry(comment.parent_id).or_default().push(comment);
+    }
+    collect(None, &mut children)
+}
+
+/// The approved comments of a post as a tree, oldest first at every level.
+#[get("/api/blog/comments")]
+pub async fn comments(
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    key: PostKey,
+    web::Query(parms): web::Query<CommentsForm>,
+) -> ApiResult {
+    let post_id = parms.post_id;
+    let post = run(&pool, move |db| db::by_post_id(db, post_id).optional())
+        .await?
+        .ok_or(ApiError::PostNotFound)?;
+    check_readable(&post, user.as_ref(), &key).await?;
+    let list = run(&pool, move |db| {
+        db::comments_of_post(
+            db,
+            post_id,
+            &[
+                CommentStatus::Approved as i32,
+                CommentStatus::Deleted as i32,
+            ],
+        )
+    })
+    .await?;
+    ok(CommentsResponse {
+        comments_closed: post.comments_closed,
+        comments: thread(list),
+    })
+}
+
+/// Comments by moderators are published right away; everything else waits in
+/// the moderation queue.
+#[post("/api/blog/new_comment")]
+pub async fn new_comment(
+    pool: web::Data<DbPool>,
+    OptionalUser(user): OptionalUser,
+    key: PostKey,
+    form: web::Json<NewCommentForm>,
+) -> ApiResult {
+    let form = form.into_inner();
+    if form.body.trim().is_empty() {
+        return Err(ApiError::InvalidInput(String::from(
+            "comment must not be empty",
+        )));
+    }
+    let author_name = match &user {
+        Some(_) => None,
+        None => match form.author_name.as_deref().map(str::trim) {
+            Some(name) if !name.is_empty() => Some(name.to_string()),
+            _ => {
+                return Err(ApiError::InvalidInput(String::from(
+                    "author_name is required for anonymous comments",
+                )))
+            }
+        },
+    };
+    let post_id = form.post_id;
+    let post = run(&pool, move |db| db::by_post_id(db, post_id).optional())
+        .await?
+        .ok_or(ApiError::PostNotFound)?;
+    check_readable(&post, user.as_ref(), &key).await?;
+    let moderator = user.as_ref().is_some_and(is_moderator);
+    if post.comments_closed && !moderator {
+        return Err(ApiError::CommentsClosed);
+    }
+    if let Some(parent_id) = form.parent_id {
+        let parent = run(&pool, move |db| db::find_comment(db, parent_id).optional()).await?;
+        let replyable = parent.is_some_and(|parent| {
+            parent.post_id == post_id && parent.status == CommentStatus::Approved as i32
+        });
+        if !replyable {
+            return Err(ApiError::CommentNotFound);
+        }
+    }
+    let status = if moderator {
+        CommentStatus::Approved
+    } else {
+        CommentStatus::Pending
+    };
+    let author = user.map(|user| user.id);
+    let comment = run(&pool, move |db| {
+        db::create_comment(
+            db,
+            &NewComment {
+                post_id,
+                parent_id: form.parent_id,
+                author,
+                author_name: author_name.as_deref(),
+                body: &form.body,
+                status: status as i32,
+            },
+        )
+    })
+    .await?;
+    created(NewCommentResponse {
+        id: comment.id,
+        status,
+    })
+}
+
+/// Edits one of the caller's comments. Edits by non-moderators go back
+/// through moderation.
+#[post("/api/blog/edit_comment")]
+pub async fn edit_comment(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<EditCommentForm>,
+) -> ApiResult {
+    let form = form.into_inner();
+    if form.body.trim().is_empty() {
+        return Err(ApiError::InvalidInput(String::from(
+            "comment must not be empty",
+        )));
+    }
+    let id = form.id;
+    let (comment, post) = run(&pool, move |db| {
+        match db::find_comment(db, id).optional()? {
+            Some(comment) => {
+                let post = db::by_post_id(db, comment.post_id)?;
+                Ok(Some((comment, post)))
+            }
+            None => Ok(None),
+        }
+    })
+    .await?
+    .ok_or(ApiError::CommentNotFound)?;
+    if comment.status == CommentStatus::Deleted as i32 {
+        return Err(ApiError::CommentNotFound);
+    }
+    if comment.author != Some(user.id) {
+        return Err(ApiError::Forbidden);
+    }
+    let moderator = is_moderator(&user);
+    if post.comments_closed && !moderator {
+        return Err(ApiError::CommentsClosed);
+    }
+    let status = if moderator {
+        CommentStatus::from_i32(comment.status)
+    } else {
+        CommentStatus::Pending
+    };
+    run(&pool, move |db| {
+        db::edit_comment(db, id, &form.body, status)
+    })
+    .await?;
+    no_content()
+}
+
+/// Deletes a comment of the caller, or any comment for moderators. Its replies
+/// stay visible under a placeholder.
+#[post("/api/blog/delete_comment")]
+pub async fn delete_comment(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    parms: web::Json<DeleteCommentForm>,
+) -> ApiResult {
+    let id = parms.id;
+    let comment = run(&pool, move |db| db::find_comment(db, id).optional())
+        .await?
+        .ok_or(ApiError::CommentNotFound)?;
+    if comment.status == CommentStatus::Deleted as i32 {
+        return Err(ApiError::CommentNotFound);
+    }
+    if comment.author != Some(user.id) && !is_moderator(&user) {
+        return Err(ApiError::Forbidden);
+    }
+    run(&pool, move |db| {
+        db::set_comment_status(db, &[id], CommentStatus::Deleted)
+    })
+    .await?;
+    no_content()
+}
+
+/// Comments awaiting moderation across all posts, oldest first.
+#[get("/api/blog/comment_queue")]
+pub async fn comment_queue(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    web::Query(parms): web::Query<CommentQueueForm>,
+) -> ApiResult {
+    require(&user, Capability::ModerateComments)?;
+    let (start, count) = paging(parms.start, parms.count)?;
+    let (count, list) = run(&pool, move |db| {
+        Ok((
+            db::count_comments_by_status(db, CommentStatus::Pending)?,
+            db::comments_by_status(db, CommentStatus::Pending, start, count)?,
+        ))
+    })
+    .await?;
+    ok(CommentQueueResponse {
+        count,
+        comments: list.into_iter().map(PublicComment::from).collect(),
+    })
+}
+
+/// Sets the status of several comments at once, e.g. to approve a batch
+/// from the queue or to reject it as spam.
+#[post("/api/blog/moderate_comments")]
+pub async fn moderate_comments(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<ModerateCommentsForm>,
+) -> ApiResult {
+    require(&user, Capability::ModerateComments)?;
+    let form = form.into_inner();
+    let count = run(&pool, move |db| {
+        db::set_comment_status(db, &form.ids, form.status)
+    })
+    .await?;
+    ok(ModerateCommentsResponse {
+        count: count as i64,
+    })
+}
+
+/// Opens or closes a post for new comments. Allowed for the post's author,
+/// moderators and those who may edit any post.
+#[post("/api/blog/close_comments")]
+pub async fn close_comments(
+    pool: web::Data<DbPool>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    form: web::Json<CloseCommentsForm>,
+) -> ApiResult {
+    let CloseCommentsForm { post_id, closed } = form.into_inner();
+    let post = run(&pool, move |db| db::by_post_id(db, post_id).optional())
+        .await?
+        .ok_or(ApiError::PostNotFound)?;
+    if post.author != user.id && !is_moderator(&user) && !user.can(Capability::EditAnyPost) {
+        return Err(ApiError::Forbidden);
+    }
+    run(&pool, move |db| {
+        db::set_comments_closed(db, post_id, closed)
+    })
+    .await?;
+    no_content()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(
+        id: i32,
+        parent_id: Option<i32>,
+        status: CommentStatus,
+    ) -> (Comment, Option<String>) {
+        let now = Utc::now().naive_utc();
+        let comment = Comment {
+            id,
+            post_id: 1,
+            parent_id,
+            author: Some(7),
+            author_name: None,
+            body: format!("comment {}", id),
+            status: status as i32,
+            created_at: now,
+            modified_at: now,
+        };
+        (comment, Some(String::from("nick")))
+    }
+
+    /// `(id, replies)` of every comment in the tree, depth first.
+    fn shape(list: &[PublicComment]) -> Vec<(i32, usize)> {
+        list.iter()
+            .flat_map(|c| std::iter::once((c.id, c.replies.len())).chain(shape(&c.replies)))
+            .collect()
+    }
+
+    #[test]
+    fn nests_replies_in_order() {
+        let tree = thread(vec![
+            comment(1, None, CommentStatus::Approved),
+            comment(2, Some(1), CommentStatus::Approved),
+            comment(3, Some(2), CommentStatus::Approved),
+            comment(4, None, CommentStatus::Approved),
+            comment(5, Some(1), CommentStatus::Approved),
+        ]);
+        assert_eq!(shape(&tree), vec![(1, 2), (2, 1), (3, 0), (5, 0), (4, 0)]);
+    }
+
+    #[test]
+    fn drops_replies_to_missing_comments() {
+        let tree = thread(vec![
+            comment(1, None, CommentStatus::Approved),
+            comment(2, Some(99), CommentStatus::Approved),
+            comment(3, Some(2), CommentStatus::Approved),
+        ]);
+        assert_eq!(shape(&tree), vec![(1, 0)]);
+    }
+
+    #[test]
+    fn keeps_deleted_comments_only_as_placeholders_for_replies() {
+        let tree = thread(vec![
+            comment(1, None, CommentStatus::Deleted),
+            comment(2, Some(1), CommentStatus::Approved),
+            comment(3, None, CommentStatus::Deleted),
+        ]);
+        assert_eq!(shape(&tree), vec![(1, 1), (2, 0)]);
+        let placeholder = &tree[0];
+        assert_eq!(placeholder.body, "");
+        assert_eq!(placeholder.author, None);
+        assert_eq!(placeholder.author_name, None);
+        assert_eq!(placeholder.replies[0].body, "comment 2");
+    }
+}
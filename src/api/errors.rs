@@ -0,0 +1,211 @@
+use std::fmt;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde::{Deserialize, Serialize};
+
+use crate::middlewares::postgresql::DbError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ApiError {
+    InvalidInput(String),
+    Unauthorized,
+    InvalidCredentials,
+    InvalidRefreshToken,
+    AccountBanned,
+    PasswordResetRequired,
+    RegistrationClosed,
+    InvalidInvite,
+    InvalidToken,
+    Forbidden,
+    PostNotFound,
+    PostPasswordRequired,
+    UserNotFound,
+    TagNotFound,
+    CommentNotFound,
+    CommentsClosed,
+    RevisionNotFound,
+    VersionRequired,
+    /// A stale `If-Match`; carries the current copy of the resource.
+    PreconditionFailed(serde_json::Value),
+    /// A stale `version` field; carries the current copy of the resource.
+    VersionConflict(serde_json::Value),
+    UsernameAlreadyExists,
+    EmailAlreadyExists,
+    SlugAlreadyExists,
+    MailFailed,
+    DatabaseError,
+}
+
+/// The JSON body of every failed request.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    /// The server's copy of a resource a write conflicted with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    /// A stable identifier clients can match on; never change an existing one.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidInput(_) => "invalid_input",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::InvalidRefreshToken => "invalid_refresh_token",
+            ApiError::AccountBanned => "account_banned",
+            ApiError::PasswordResetRequired => "password_reset_required",
+            ApiError::RegistrationClosed => "registration_closed",
+            ApiError::InvalidInvite => "invalid_invite",
+            ApiError::InvalidToken => "invalid_token",
+            ApiError::Forbidden => "forbidden",
+            ApiError::PostNotFound => "post_not_found",
+            ApiError::PostPasswordRequired => "post_password_required",
+            ApiError::UserNotFound => "user_not_found",
+            ApiError::TagNotFound => "tag_not_found",
+            ApiError::CommentNotFound => "comment_not_found",
+            ApiError::CommentsClosed => "comments_closed",
+            ApiError::RevisionNotFound => "revision_not_found",
+            ApiError::VersionRequired => "version_required",
+            ApiError::PreconditionFailed(_) => "precondition_failed",
+            ApiError::VersionConflict(_) => "version_conflict",
+            ApiError::UsernameAlreadyExists => "username_already_exists",
+            ApiError::EmailAlreadyExists => "email_already_exists",
+            ApiError::SlugAlreadyExists => "slug_already_exists",
+            ApiError::MailFailed => "mail_failed",
+            ApiError::DatabaseError => "database_error",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::InvalidInput(reason) => write!(f, "invalid input: {}", reason),
+            ApiError::Unauthorized => write!(f, "missing, invalid or expired token"),
+            ApiError::InvalidCredentials => write!(f, "wrong username or password"),
+            ApiError::InvalidRefreshToken => {
+                write!(f, "refresh token is invalid, expired or revoked")
+            }
+            ApiError::AccountBanned => write!(f, "account is banned"),
+            ApiError::PasswordResetRequired => {
+                write!(
+                    f,
+                    "a new password must be set through the password reset mail"
+                )
+            }
+            ApiError::RegistrationClosed => write!(f, "registration is closed"),
+            ApiError::InvalidInvite => {
+                write!(f, "invite code is invalid, expired or already used")
+            }
+            ApiError::InvalidToken => write!(f, "token is invalid, expired or already used"),
+            ApiError::Forbidden => write!(f, "insufficient permission"),
+            ApiError::PostNotFound => write!(f, "post not found"),
+            ApiError::PostPasswordRequired => {
+                write!(f, "post is password-protected; send X-Post-Password")
+            }
+            ApiError::UserNotFound => write!(f, "user not found"),
+            ApiError::TagNotFound => write!(f, "tag not found"),
+            ApiError::CommentNotFound => write!(f, "comment not found"),
+            ApiError::CommentsClosed => write!(f, "comments are closed for this post"),
+            ApiError::RevisionNotFound => write!(f, "revision not found"),
+            ApiError::VersionRequired => {
+                write!(f, "an If-Match header or a version field is required")
+            }
+            ApiError::PreconditionFailed(_) | ApiError::VersionConflict(_) => {
+                write!(f, "the resource was modified since it was fetched")
+            }
+            ApiError::UsernameAlreadyExists => write!(f, "username is already taken"),
+            ApiError::EmailAlreadyExists => write!(f, "email is already registered"),
+            ApiError::SlugAlreadyExists => write!(f, "slug is used by another post"),
+            ApiError::MailFailed => write!(f, "mail could not be sent"),
+            ApiError::DatabaseError => write!(f, "database error"),
+        }
+    }
+}
+
+/// Unique constraints whose violation means the client picked a name that
+/// is already taken, rather than a server fault.
+const CONFLICTS: &[(&str, ApiError)] = &[
+    ("users_username_key", ApiError::UsernameAlreadyExists),
+    ("users_email_key", ApiError::EmailAlreadyExists),
+    ("posts_slug_key", ApiError::SlugAlreadyExists),
+];
+
+impl From<DbError> for ApiError {
+    fn from(e: DbError) -> Self {
+        if let DbError::Query(DieselError::DatabaseError(
+            DatabaseErrorKind::UniqueViolation,
+            info,
+        )) = &e
+        {
+            let conflict = CONFLICTS
+                .iter()
+                .find(|(constraint, _)| info.constraint_name() == Some(constraint));
+            if let Some((_, error)) = conflict {
+                return error.clone();
+            }
+        }
+        log::error!("database error: {:?}", e);
+        ApiError::DatabaseError
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidInput(_) | ApiError::InvalidToken => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized
+            | ApiError::InvalidCredentials
+            | ApiError::InvalidRefreshToken => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden
+            | ApiError::CommentsClosed
+            | ApiError::PostPasswordRequired
+            | ApiError::AccountBanned
+            | ApiError::PasswordResetRequired
+            | ApiError::RegistrationClosed
+            | ApiError::InvalidInvite => StatusCode::FORBIDDEN,
+            ApiError::PostNotFound
+            | ApiError::UserNotFound
+            | ApiError::TagNotFound
+            | ApiError::CommentNotFound
+            | ApiError::RevisionNotFound => StatusCode::NOT_FOUND,
+            ApiError::UsernameAlreadyExists
+            | ApiError::EmailAlreadyExists
+            | ApiError::SlugAlreadyExists
+            | ApiError::VersionConflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::VersionRequired => StatusCode::PRECONDITION_REQUIRED,
+            ApiError::MailFailed => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut res = HttpResponse::build(self.status_code());
+        if *self == ApiError::Unauthorized {
+            res.header(header::WWW_AUTHENTICATE, "Bearer");
+        }
+        let body = ErrorBody {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            current: match self {
+                ApiError::PreconditionFailed(current) | ApiError::VersionConflict(current) => {
+                    Some(current.clone())
+                }
+                _ => None,
+            },
+        };
+        if super::use_envelope() {
+            res.json(super::ResponseBlock {
+                status: false,
+                body: Some(body),
+            })
+        } else {
+            res.json(body)
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use actix_web::http::header;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
+use serde::Deserialize;
+use sha3::{Digest, Sha3_256};
+
+use crate::api::errors::ApiError;
+use crate::api::ApiResult;
+use crate::db;
+use crate::db::models::Post;
+use crate::middlewares::markdown;
+use crate::middlewares::postgresql::{run, DbPool};
+use crate::CONFIG;
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct FeedQuery {
+    pub tag: Option<String>,
+}
+
+struct FeedItem {
+    post: Post,
+    author: String,
+    tags: Vec<String>,
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn blog_url() -> String {
+    CONFIG.blog.url.trim_end_matches('/').to_string()
+}
+
+fn post_link(post: &Post) -> String {
+    let path = CONFIG
+        .feed
+        .post_path
+        .replace("{id}", &post.id.to_string())
+        .replace("{slug}", &post.slug);
+    format!("{}/{}", blog_url(), path.trim_start_matches('/'))
+}
+
+fn feed_link(file: &str, tag: Option<&str>) -> String {
+    let mut link = format!("{}/{}", blog_url(), file);
+    if let Some(tag) = tag {
+        link.push_str("?tag=");
+        for b in tag.bytes() {
+            match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    link.push(b as char)
+                }
+                _ => write!(link, "%{:02X}", b).unwrap(),
+            }
+        }
+    }
+    link
+}
+
+fn feed_title(tag: Option<&str>) -> String {
+    match tag {
+        Some(tag) => format!("{} - {}", CONFIG.blog.name, tag),
+        None => CONFIG.blog.name.clone(),
+    }
+}
+
+/// The sanitized HTML of the post, or the first `excerpt_length` characters
+/// of its plain text cut at a word boundary when `feed.full_content` is off.
+fn content(post: &Post) -> String {
+    if CONFIG.feed.full_content {
+        return post
+            .body_html
+            .clone()
+            .unwrap_or_else(|| markdown::render(&post.body));
+    }
+    markdown::truncate(
+        &markdown::plain_text(&post.body),
+        CONFIG.feed.excerpt_length,
+    )
+}
+
+fn utc(t: NaiveDateTime) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&t)
+}
+
+/// When an item last changed for a feed reader: publishing a post counts,
+/// even when its content is older.
+fn last_change(item: &FeedItem) -> NaiveDateTime {
+    item.post
+        .published_at
+        .map_or(item.post.modified_at, |at| at.max(item.post.modified_at))
+}
+
+fn render_rss(items: &[FeedItem], tag: Option<&str>) -> String {
+    let mut xml = String::new();
+    xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom"><channel>"#);
+    write!(
+        xml,
+        "<title>{title}</title><link>{link}</link><description>{title}</description>\
+         <atom:link href=\"{feed}\" rel=\"self\" type=\"application/rss+xml\"/>",
+        title = escape(&feed_title(tag)),
+        link = escape(&blog_url()),
+        feed = escape(&feed_link("feed.rss", tag)),
+    )
+    .unwrap();
+    if let Some(updated) = items.iter().map(last_change).max() {
+        write!(
+            xml,
+            "<lastBuildDate>{}</lastBuildDate>",
+            utc(updated).to_rfc2822()
+        )
+        .unwrap();
+    }
+    for item in items {
+        let link = post_link(&item.post);
+        write!(
+            xml,
+            "<item><title>{}</title><link>{}</link><guid isPermaLink=\"true\">{}</guid>\
+             <pubDate>{}</pubDate><description>{}</description>",
+            escape(&item.post.title),
+            escape(&link),
+            escape(&link),
+            utc(item.post.published_at.unwrap_or(item.post.created_at)).to_rfc2822(),
+            escape(&content(&item.post)),
+        )
+        .unwrap();
+        for tag in &item.tags {
+            write!(xml, "<category>{}</category>", escape(tag)).unwrap();
+        }
+        xml.push_str("</item>");
+    }
+    xml.push_str("</channel></rss>");
+    xml
+}
+
+fn render_atom(items: &[FeedItem], tag: Option<&str>) -> String {
+    let self_link = feed_link("feed.atom", tag);
+    let updated = items.iter().map(last_change).max().unwrap_or_default();
+    let mut xml = String::new();
+    xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+    write!(
+        xml,
+        "<id>{self_link}</id><title>{}</title><updated>{}</updated>\
+         <link rel=\"self\" href=\"{self_link}\"/><link rel=\"alternate\" href=\"{}\"/>\
+         <author><name>{}</name></author>",
+        escape(&feed_title(tag)),
+        utc(updated).to_rfc3339(),
+        escape(&blog_url()),
+        escape(&CONFIG.blog.name),
+        self_link = escape(&self_link),
+    )
+    .unwrap();
+    for item in items {
+        let link = escape(&post_link(&item.post));
+        write!(
+            xml,
+            "<entry><id>{link}</id><title>{}</title><link rel=\"alternate\" href=\"{link}\"/>\
+             <published>{}</published><updated>{}</updated><author><name>{}</name></author>",
+            escape(&item.post.title),
+            utc(item.post.published_at.unwrap_or(item.post.created_at)).to_rfc3339(),
+            utc(last_change(item)).to_rfc3339(),
+            escape(&item.author),
+            link = link,
+        )
+        .unwrap();
+        for tag in &item.tags {
+            write!(xml, "<category term=\"{}\"/>", escape(tag)).unwrap();
+        }
+        let (kind, content_type) = if CONFIG.feed.full_content {
+            ("content", "html")
+        } else {
+            ("summary", "text")
+        };
+        write!(
+            xml,
+            "<{kind} type=\"{content_type}\">{}</{kind}></entry>",
+            escape(&content(&item.post)),
+            kind = kind,
+            content_type = content_type,
+        )
+        .unwrap();
+    }
+    xml.push_str("</feed>");
+    xml
+}
+
+async fn load_items(pool: &DbPool, tag: Option<String>) -> Result<Vec<FeedItem>, ApiError> {
+    let count = CONFIG.feed.items;
+    let (posts, tags) = run(pool, move |db| {
+        let posts = db::feed_posts(db, tag.as_deref(), count)?;
+        let ids: Vec<i32> = posts.iter().map(|(post, _)| post.id).collect();
+        Ok((posts, db::tags_of_posts(db, &ids)?))
+    })
+    .await?;
+    let mut tags_by_post: HashMap<i32, Vec<String>> = HashMap::new();
+    for (id, name) in tags {
+        tags_by_post.entry(id).or_default().push(name);
+    }
+    Ok(posts
+        .into_iter()
+        .map(|(post, author)| FeedItem {
+            tags: tags_by_post.remove(&post.id).unwrap_or_default(),
+            post,
+            author,
+        })
+        .collect())
+}
+
+/// Sends `body` with `ETag` and `Last-Modified` validators, or a bare 304 if
+/// the client's cached copy is still current.
+fn conditional(
+    req: &HttpRequest,
+    content_type: &str,
+    items: &[FeedItem],
+    body: String,
+) -> ApiResult {
+    let mut hasher = Sha3_256::new();
+    hasher.update(body.as_bytes());
+    let etag = format!("\"{}\"", &hex::encode(hasher.finalize())[..32]);
+    let newest = items.iter().map(last_change).max();
+    let last_modified = newest.map(|t| utc(t).format("%a, %d %b %Y %H:%M:%S GMT").to_string());
+
+    let headers = req.headers();
+    let not_modified = match headers.get(header::IF_NONE_MATCH) {
+        Some(value) => value
+            .to_str()
+            .map(|v| v.split(',').any(|t| t.trim() == etag || t.trim() == "*"))
+            .unwrap_or(false),
+        None => match (headers.get(header::IF_MODIFIED_SINCE), newest) {
+            (Some(since), Some(newest)) => since
+                .to_str()
+                .ok()
+                .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+                .map(|since| utc(newest).timestamp() <= since.timestamp())
+                .unwrap_or(false),
+            _ => false,
+        },
+    };
+
+    let mut res = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    res.header(header::ETAG, etag.as_str());
+    if let Some(last_modified) = &last_modified {
+        res.header(header::LAST_MODIFIED, last_modified.as_str());
+    }
+    Ok(if not_modified {
+        res.finish()
+    } else {
+        res.content_type(content_type).body(body)
+    })
+}
+
+#[get("/feed.rss")]
+pub async fn rss(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    web::Query(parms): web::Query<FeedQuery>,
+) -> ApiResult {
+    let items = load_items(&pool, parms.tag.clone()).await?;
+    let body = render_rss(&items, parms.tag.as_deref());
+    conditional(&req, "application/rss+xml; charset=utf-8", &items, body)
+}
+
+#[get("/feed.atom")]
+pub async fn atom(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    web::Query(parms): web::Query<FeedQuery>,
+) -> ApiResult {
+    let items = load_items(&pool, parms.tag.clone()).await?;
+    let body = render_atom(&items, parms.tag.as_deref());
+    conditional(&req, "application/atom+xml; charset=utf-8", &items, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    fn item(modified_at: NaiveDateTime, published_at: NaiveDateTime) -> FeedItem {
+        FeedItem {
+            post: Post {
+                id: 1,
+                title: String::from("title"),
+                body: String::from("body"),
+                author: 1,
+                created_at: modified_at,
+                modified_at,
+                comments_closed: false,
+                status: 2,
+                published_at: Some(published_at),
+                revision: 1,
+                body_html: None,
+                slug: String::from("title"),
+                visibility: 0,
+                password_hash: None,
+                share_token: None,
+            },
+            author: String::from("author"),
+            tags: Vec::new(),
+        }
+    }
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2021, 3, 1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn respond(headers: &[(header::HeaderName, &str)], items: &[FeedItem]) -> HttpResponse {
+        let mut req = TestRequest::default();
+        for (name, value) in headers {
+            req = req.header(name.clone(), *value);
+        }
+        conditional(
+            &req.to_http_request(),
+            "text/xml",
+            items,
+            String::from("<feed/>"),
+        )
+        .unwrap()
+    }
+
+    fn etag(items: &[FeedItem]) -> String {
+        let res = respond(&[], items);
+        res.headers()
+            .get(header::ETAG)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+        assert_eq!(escape("plain 한글"), "plain 한글");
+    }
+
+    #[test]
+    fn sends_validators() {
+        let items = [item(at(10), at(12))];
+        let res = respond(&[], &items);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().contains_key(header::ETAG));
+        assert_eq!(
+            res.headers().get(header::LAST_MODIFIED).unwrap(),
+            "Mon, 01 Mar 2021 12:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn matching_etag_is_not_modified() {
+        let items = [item(at(10), at(12))];
+        let tag = etag(&items);
+        let res = respond(
+            &[(header::IF_NONE_MATCH, &format!("\"x\", {}", tag))],
+            &items,
+        );
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        let res = respond(&[(header::IF_NONE_MATCH, "*")], &items);
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn etag_takes_precedence_over_date() {
+        let items = [item(at(10), at(12))];
+        let res = respond(
+            &[
+                (header::IF_NONE_MATCH, "\"stale\""),
+                (header::IF_MODIFIED_SINCE, "Mon, 01 Mar 2021 13:00:00 GMT"),
+            ],
+            &items,
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn modified_since_compares_the_latest_change() {
+        let items = [item(at(10), at(12))];
+        let since = |value| respond(&[(header::IF_MODIFIED_SINCE, value)], &items).status();
+        assert_eq!(
+            since("Mon, 01 Mar 2021 12:00:00 GMT"),
+            StatusCode::NOT_MODIFIED
+        );
+        // Published after the date even though it was last edited before.
+        assert_eq!(since("Mon, 01 Mar 2021 11:00:00 GMT"), StatusCode::OK);
+        assert_eq!(since("not a date"), StatusCode::OK);
+        let res = respond(
+            &[(header::IF_MODIFIED_SINCE, "Mon, 01 Mar 2021 12:00:00 GMT")],
+            &[],
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
@@ -1,2 +1,75 @@
 pub mod account_service;
 pub mod blog_service;
+pub mod comment_service;
+pub mod errors;
+pub mod feed_service;
+
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+
+use crate::api::errors::ApiError;
+use crate::CONFIG;
+
+/// The envelope every response used to be wrapped in. Still emitted when
+/// `server.response_envelope` is enabled, for clients that rely on it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ResponseBlock<T> {
+    pub status: bool,
+    pub body: Option<T>,
+}
+
+pub type ApiResult = Result<HttpResponse, errors::ApiError>;
+
+fn use_envelope() -> bool {
+    CONFIG.server.response_envelope
+}
+
+fn respond<T: Serialize>(mut res: actix_web::dev::HttpResponseBuilder, body: T) -> ApiResult {
+    Ok(if use_envelope() {
+        res.json(ResponseBlock {
+            status: true,
+            body: Some(body),
+        })
+    } else {
+        res.json(body)
+    })
+}
+
+pub fn ok<T: Serialize>(body: T) -> ApiResult {
+    respond(HttpResponse::Ok(), body)
+}
+
+pub fn created<T: Serialize>(body: T) -> ApiResult {
+    respond(HttpResponse::Created(), body)
+}
+
+/// 204 for successful requests with nothing to return, or an empty
+/// `ResponseBlock` when the envelope is enabled.
+pub fn no_content() -> ApiResult {
+    if use_envelope() {
+        ok(())
+    } else {
+        Ok(HttpResponse::NoContent().finish())
+    }
+}
+
+/// `count` capped at `blog.max_page_size`.
+pub fn page_size(count: i64) -> Result<i64, ApiError> {
+    if count < 1 {
+        return Err(ApiError::InvalidInput(String::from(
+            "count must be at least 1",
+        )));
+    }
+    Ok(count.min(CONFIG.blog.max_page_size))
+}
+
+/// The offset and size of a page of results, with the size capped like
+/// `page_size`.
+pub fn paging(start: i64, count: i64) -> Result<(i64, i64), ApiError> {
+    if start < 0 {
+        return Err(ApiError::InvalidInput(String::from(
+            "start must not be negative",
+        )));
+    }
+    Ok((start, page_size(count)?))
+}
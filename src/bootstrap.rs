@@ -0,0 +1,120 @@
+use std::env;
+use std::io::{self, BufRead, Write};
+
+use diesel::pg::PgConnection;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+use crate::db;
+use crate::db::models::AccountLevel;
+use crate::middlewares::postgresql::DbPool;
+use crate::CONFIG;
+
+const USAGE: &str = "usage: blog-backend create-admin <username> <email> [nickname]";
+
+fn io_error<E: ToString>(e: E) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+fn create_admin(
+    db: &PgConnection,
+    username: &str,
+    pass: &str,
+    email: &str,
+    nickname: &str,
+) -> io::Result<()> {
+    if pass.is_empty() {
+        return Err(io_error("password must not be empty"));
+    }
+    match db::register(
+        db,
+        username,
+        pass,
+        email,
+        nickname,
+        AccountLevel::Admin,
+        None,
+    ) {
+        Ok(_) => Ok(()),
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Err(io_error(
+            format!("username {} or email {} is already taken", username, email),
+        )),
+        Err(e) => Err(io_error(e)),
+    }
+}
+
+/// `blog-backend create-admin <username> <email> [nickname]`. The password
+/// comes from `BLOG_ADMIN_PASSWORD`, or from the first line of stdin so it
+/// does not end up in the shell history.
+pub fn create_admin_command(pool: &DbPool, args: &[String]) -> io::Result<()> {
+    let (username, email) = match args {
+        [username, email] | [username, email, _] => (username, email),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, USAGE)),
+    };
+    let nickname = args.get(2).unwrap_or(username);
+    let pass = match env::var("BLOG_ADMIN_PASSWORD") {
+        Ok(pass) => pass,
+        Err(_) => {
+            eprint!("password: ");
+            io::stderr().flush()?;
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            line.trim_end_matches(&['\r', '\n'][..]).to_string()
+        }
+    };
+    let db = pool.get().map_err(io_error)?;
+    create_admin(&db, username, &pass, email, nickname)?;
+    println!("created admin {}", username);
+    Ok(())
+}
+
+/// Fails unless the database knows `blog.timezone`, which archives and
+/// date filters are computed in.
+pub fn check_timezone(pool: &DbPool) -> io::Result<()> {
+    let zone = &CONFIG.blog.timezone;
+    pool.get()
+        .map_err(io_error)
+        .and_then(|db| db::check_timezone(&db, zone).map_err(io_error))
+        .map_err(|e| io_error(format!("blog.timezone {:?} is not usable: {}", zone, e)))
+}
+
+/// Gives posts from before slugs existed a slug made from their title.
+pub fn backfill_slugs(pool: &DbPool) {
+    match pool
+        .get()
+        .map_err(io_error)
+        .and_then(|db| db::backfill_slugs(&db).map_err(io_error))
+    {
+        Ok(0) => {}
+        Ok(count) => log::info!("made slugs for {} posts from their titles", count),
+        Err(e) => log::error!("could not make slugs for older posts: {}", e),
+    }
+}
+
+/// Creates the admin described by `BLOG_ADMIN_USERNAME`, `BLOG_ADMIN_EMAIL`,
+/// `BLOG_ADMIN_PASSWORD` and optionally `BLOG_ADMIN_NICKNAME`, unless an
+/// account with that username already exists. Does nothing when they are
+/// not set.
+pub fn admin_from_env(pool: &DbPool) {
+    let (username, email, pass) = match (
+        env::var("BLOG_ADMIN_USERNAME"),
+        env::var("BLOG_ADMIN_EMAIL"),
+        env::var("BLOG_ADMIN_PASSWORD"),
+    ) {
+        (Ok(username), Ok(email), Ok(pass)) => (username, email, pass),
+        _ => return,
+    };
+    let nickname = env::var("BLOG_ADMIN_NICKNAME").unwrap_or_else(|_| username.clone());
+    let result = pool.get().map_err(io_error).and_then(|db| {
+        if db::by_username(&db, &username)
+            .map_err(io_error)?
+            .is_empty()
+        {
+            create_admin(&db, &username, &pass, &email, &nickname)?;
+            log::info!("created admin {} from the environment", username);
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        log::error!("could not create admin {}: {}", username, e);
+    }
+}
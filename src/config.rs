@@ -7,19 +7,142 @@ pub struct Config {
     pub server: ServerConfig,
     pub blog: BlogConfig,
     pub secret: SecretConfig,
+    #[serde(default)]
+    pub security: SecurityConfig,
+    #[serde(default)]
+    pub feed: FeedConfig,
+    #[serde(default)]
+    pub registration: RegistrationConfig,
+    #[serde(default)]
+    pub mail: MailConfig,
 }
 
-#[derive(Clone, Deserialize, Debug, Default)]
+#[derive(Clone, Deserialize, Debug)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
     pub database_url: String,
+    #[serde(default = "default_pool_max_size")]
+    pub pool_max_size: u32,
+    #[serde(default)]
+    pub pool_min_idle: Option<u32>,
+    /// Seconds to wait for a free connection before failing the request.
+    #[serde(default = "default_pool_connection_timeout")]
+    pub pool_connection_timeout: u64,
+    /// Seconds an idle connection is kept open before being closed.
+    #[serde(default = "default_pool_idle_timeout")]
+    pub pool_idle_timeout: u64,
+    /// Wrap every response in the legacy `{ status, body }` envelope.
+    #[serde(default)]
+    pub response_envelope: bool,
+    /// Seconds between checks for scheduled posts that are due.
+    #[serde(default = "default_publish_interval")]
+    pub publish_interval: u64,
 }
 
-#[derive(Clone, Deserialize, Debug, Default)]
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            port: 0,
+            database_url: String::new(),
+            pool_max_size: default_pool_max_size(),
+            pool_min_idle: None,
+            pool_connection_timeout: default_pool_connection_timeout(),
+            pool_idle_timeout: default_pool_idle_timeout(),
+            response_envelope: false,
+            publish_interval: default_publish_interval(),
+        }
+    }
+}
+
+fn default_pool_max_size() -> u32 {
+    10
+}
+
+fn default_pool_connection_timeout() -> u64 {
+    5
+}
+
+fn default_pool_idle_timeout() -> u64 {
+    600
+}
+
+fn default_publish_interval() -> u64 {
+    60
+}
+
+#[derive(Clone, Deserialize, Debug)]
 pub struct BlogConfig {
     pub name: String,
     pub url: String,
+    /// Highlight fenced code blocks when rendering posts. Only affects posts
+    /// saved after it is changed.
+    #[serde(default = "default_highlight_code")]
+    pub highlight_code: bool,
+    /// Theme served by the highlight stylesheet when none is asked for.
+    #[serde(default = "default_highlight_theme")]
+    pub highlight_theme: String,
+    /// Characters of plain text in the excerpts shown in post listings.
+    #[serde(default = "default_excerpt_length")]
+    pub excerpt_length: usize,
+    /// Reading speed the reading time of a post is estimated with.
+    #[serde(default = "default_words_per_minute")]
+    pub words_per_minute: usize,
+    /// Posts per page of a listing when the client does not ask for a count.
+    #[serde(default = "default_page_size")]
+    pub page_size: i64,
+    /// The most posts a page of a listing may hold.
+    #[serde(default = "default_max_page_size")]
+    pub max_page_size: i64,
+    /// Time zone the archive groups posts by month in, as a name PostgreSQL
+    /// knows, e.g. `Asia/Seoul`.
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+}
+
+impl Default for BlogConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            url: String::new(),
+            highlight_code: default_highlight_code(),
+            highlight_theme: default_highlight_theme(),
+            excerpt_length: default_excerpt_length(),
+            words_per_minute: default_words_per_minute(),
+            page_size: default_page_size(),
+            max_page_size: default_max_page_size(),
+            timezone: default_timezone(),
+        }
+    }
+}
+
+fn default_highlight_code() -> bool {
+    true
+}
+
+fn default_highlight_theme() -> String {
+    String::from("InspiredGitHub")
+}
+
+fn default_excerpt_length() -> usize {
+    200
+}
+
+fn default_words_per_minute() -> usize {
+    200
+}
+
+fn default_page_size() -> i64 {
+    20
+}
+
+fn default_max_page_size() -> i64 {
+    100
+}
+
+fn default_timezone() -> String {
+    String::from("UTC")
 }
 
 #[derive(Clone, Deserialize, Debug, Default)]
@@ -27,6 +150,137 @@ pub struct SecretConfig {
     pub secret: String,
 }
 
+/// Argon2id cost parameters used when hashing account passwords, and the
+/// lifetimes of issued tokens. Changing the Argon2 parameters makes existing
+/// hashes get upgraded on the next login.
+#[derive(Clone, Deserialize, Debug)]
+#[serde(default)]
+pub struct SecurityConfig {
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    pub access_token_ttl_minutes: u64,
+    pub refresh_token_ttl_days: u64,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            argon2_memory_kib: 19456,
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+            access_token_ttl_minutes: 15,
+            refresh_token_ttl_days: 30,
+        }
+    }
+}
+
+/// Settings for the RSS and Atom feeds.
+#[derive(Clone, Deserialize, Debug)]
+#[serde(default)]
+pub struct FeedConfig {
+    /// Number of posts in each feed.
+    pub items: i64,
+    /// Put the whole post body in the feed instead of an excerpt.
+    pub full_content: bool,
+    /// Length of the excerpt in characters when `full_content` is off.
+    pub excerpt_length: usize,
+    /// Path of a post on the frontend, relative to `blog.url`. `{id}` and
+    /// `{slug}` are replaced with the post's id and slug.
+    pub post_path: String,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        Self {
+            items: 20,
+            full_content: false,
+            excerpt_length: 300,
+            post_path: String::from("/posts/{id}"),
+        }
+    }
+}
+
+/// Who may create an account through `register`.
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationMode {
+    #[default]
+    Open,
+    /// Only with an invite code from an admin.
+    Invite,
+    /// Nobody. Accounts can still be made with `create-admin`.
+    Closed,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+#[serde(default)]
+pub struct RegistrationConfig {
+    pub mode: RegistrationMode,
+    /// Days an invite code stays usable.
+    pub invite_ttl_days: i64,
+}
+
+impl Default for RegistrationConfig {
+    fn default() -> Self {
+        Self {
+            mode: RegistrationMode::default(),
+            invite_ttl_days: 7,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    Smtp,
+    /// Write every mail as an `.eml` file into `outbox` instead of sending it.
+    #[default]
+    File,
+}
+
+/// How account mails (email verification, password reset) are sent, and
+/// where the links in them point.
+#[derive(Clone, Deserialize, Debug)]
+#[serde(default)]
+pub struct MailConfig {
+    pub transport: MailTransport,
+    /// The `From` address, e.g. `Blog <blog@example.com>`.
+    pub from: String,
+    pub outbox: String,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    /// Connect with implicit TLS instead of upgrading with STARTTLS.
+    pub smtp_implicit_tls: bool,
+    /// Paths on the frontend, relative to `blog.url`, that complete each
+    /// flow. `{token}` is replaced with the token.
+    pub verify_email_path: String,
+    pub reset_password_path: String,
+    pub verify_email_ttl_hours: i64,
+    pub reset_password_ttl_minutes: i64,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            transport: MailTransport::default(),
+            from: String::from("blog@localhost"),
+            outbox: String::from("outbox"),
+            smtp_host: String::from("localhost"),
+            smtp_port: 587,
+            smtp_username: None,
+            smtp_password: None,
+            smtp_implicit_tls: false,
+            verify_email_path: String::from("/verify-email?token={token}"),
+            reset_password_path: String::from("/reset-password?token={token}"),
+            verify_email_ttl_hours: 48,
+            reset_password_ttl_minutes: 60,
+        }
+    }
+}
+
 pub fn load_config(path: &str) -> std::io::Result<Config> {
     let mut f = File::open(path)?;
     let mut buf = String::new();
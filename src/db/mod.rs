@@ -1,26 +1,35 @@
+// diesel 1.x derives expand to impls nested inside consts, which rustc now lints.
+#![allow(non_local_definitions)]
+
 pub mod models;
 pub mod schema;
 
-use crate::api::account_service::errors::AccountError;
-use crate::middlewares::postgresql::establish_connection;
+use crate::middlewares::auth::random_hex;
+use crate::middlewares::markdown;
+use crate::middlewares::password::{self, Verification};
+use crate::middlewares::slug::slugify;
+use crate::CONFIG;
 use chrono::prelude::*;
+use diesel::dsl::sql;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
+use diesel::sql_types::{Array, BigInt, Bool, Date, Integer, Nullable, Text};
 use models::*;
 use schema::*;
-use serde::{Deserialize, Serialize};
-use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
 
+/// Creates an account. With an `invite`, the account uses it up, and
+/// nothing is created (`None`) unless the invite is still valid.
 pub fn register<'a>(
+    db: &PgConnection,
     username: &'a str,
     pass: &'a str,
     email: &'a str,
     nickname: &'a str,
     permission: AccountLevel,
-) -> QueryResult<User> {
-    let db = establish_connection();
-    let mut hasher = Sha3_256::new();
-    hasher.update(pass.as_bytes());
-    let pass_hashed = hex::encode(hasher.finalize());
+    invite: Option<&'a str>,
+) -> QueryResult<Option<User>> {
+    let pass_hashed = password::hash(pass).map_err(hash_error)?;
     let new_user = Register {
         username,
         pass: &pass_hashed,
@@ -28,122 +37,1377 @@ pub fn register<'a>(
         nickname,
         permission: permission as i32,
     };
-    diesel::insert_into(users::table)
-        .values(&new_user)
-        .get_result(&db)
+    db.transaction(|| {
+        let now = Utc::now().naive_utc();
+        let invite = match invite {
+            Some(code) => {
+                let claimed = diesel::update(
+                    invites::table
+                        .filter(invites::code.eq(code))
+                        .filter(invites::used_at.is_null())
+                        .filter(invites::expires_at.gt(now)),
+                )
+                .set(invites::used_at.eq(now))
+                .execute(db)?;
+                if claimed == 0 {
+                    return Ok(None);
+                }
+                Some(code)
+            }
+            None => None,
+        };
+        let user: User = diesel::insert_into(users::table)
+            .values(&new_user)
+            .get_result(db)?;
+        if let Some(code) = invite {
+            diesel::update(invites::table.find(code))
+                .set(invites::used_by.eq(user.id))
+                .execute(db)?;
+        }
+        Ok(Some(user))
+    })
 }
 
-pub fn login<'a>(username: &'a str, pass: &'a str) -> QueryResult<(AccountError, i32)> {
-    let db = establish_connection();
-    let mut hasher = Sha3_256::new();
-    hasher.update(pass.as_bytes());
-    let pass_hashed = hasher.finalize();
+/// Returns the user if `pass` matches, upgrading a legacy or outdated hash
+/// in place.
+pub fn login<'a>(db: &PgConnection, username: &'a str, pass: &'a str) -> QueryResult<Option<User>> {
     let mut items = users::table
         .filter(users::dsl::username.eq(username))
-        .load::<User>(&db)?;
+        .load::<User>(db)?;
     if let Some(user) = items.pop() {
-        if hex::encode(pass_hashed) == user.pass {
-            Ok((AccountError::Nothing, user.id))
-        } else {
-            Ok((AccountError::PassNotMatched, -1))
+        match password::verify(pass, &user.pass).map_err(hash_error)? {
+            Verification::Valid => Ok(Some(user)),
+            Verification::NeedsRehash => {
+                let pass_hashed = password::hash(pass).map_err(hash_error)?;
+                diesel::update(users::table.find(user.id))
+                    .set(users::pass.eq(&pass_hashed))
+                    .execute(db)?;
+                Ok(Some(user))
+            }
+            Verification::Invalid => Ok(None),
         }
     } else {
-        Ok((AccountError::UserNotExists, -1))
+        Ok(None)
     }
 }
 
-pub fn find_user<'a>(pk: i32) -> QueryResult<User> {
-    let db = establish_connection();
-    users::table.find(pk).first(&db)
+fn hash_error(e: password::Error) -> diesel::result::Error {
+    diesel::result::Error::SerializationError(Box::new(e))
+}
+
+pub fn find_user(db: &PgConnection, pk: i32) -> QueryResult<User> {
+    users::table.find(pk).first(db)
+}
+
+pub fn set_user_level(db: &PgConnection, pk: i32, level: AccountLevel) -> QueryResult<usize> {
+    diesel::update(users::table.find(pk))
+        .set(users::permission.eq(level as i32))
+        .execute(db)
 }
 
-pub fn by_username<'a>(username: &'a str) -> QueryResult<Vec<User>> {
-    let db = establish_connection();
+sql_function!(fn lower(x: Text) -> Text);
+
+/// The user registered with `email`, ignoring case like the unique index
+/// on emails does.
+pub fn user_by_email(db: &PgConnection, email: &str) -> QueryResult<Option<User>> {
     users::table
-        .filter(users::dsl::username.eq(username))
-        .load::<User>(&db)
+        .filter(lower(users::email).eq(email.to_lowercase()))
+        .first(db)
+        .optional()
+}
+
+/// Replaces a user's email, which then needs verifying again. Password
+/// reset links mailed to the old address stop working.
+pub fn set_email(db: &PgConnection, pk: i32, email: &str) -> QueryResult<User> {
+    db.transaction(|| {
+        let user = diesel::update(users::table.find(pk))
+            .set((users::email.eq(email), users::email_verified.eq(false)))
+            .get_result(db)?;
+        retire_one_time_tokens(db, pk, TokenPurpose::ResetPassword as i32)?;
+        Ok(user)
+    })
+}
+
+/// What a user shows about themselves on their author page.
+pub struct ProfileEdit<'a> {
+    pub nickname: &'a str,
+    pub bio: &'a str,
+    pub avatar_url: Option<&'a str>,
+    pub links: &'a [String],
+}
+
+pub fn set_profile(db: &PgConnection, pk: i32, profile: &ProfileEdit) -> QueryResult<usize> {
+    diesel::update(users::table.find(pk))
+        .set((
+            users::nickname.eq(profile.nickname),
+            users::bio.eq(profile.bio),
+            users::avatar_url.eq(profile.avatar_url),
+            users::links.eq(profile.links),
+        ))
+        .execute(db)
+}
+
+/// Marks `email` as verified, unless the user has changed it since.
+pub fn set_email_verified(db: &PgConnection, pk: i32, email: &str) -> QueryResult<usize> {
+    diesel::update(
+        users::table
+            .filter(users::id.eq(pk))
+            .filter(users::email.eq(email)),
+    )
+    .set(users::email_verified.eq(true))
+    .execute(db)
+}
+
+/// Marks the user's unused tokens for `purpose` as used.
+fn retire_one_time_tokens(db: &PgConnection, user_id: i32, purpose: i32) -> QueryResult<usize> {
+    diesel::update(
+        one_time_tokens::table
+            .filter(one_time_tokens::user_id.eq(user_id))
+            .filter(one_time_tokens::purpose.eq(purpose))
+            .filter(one_time_tokens::used_at.is_null()),
+    )
+    .set(one_time_tokens::used_at.eq(Utc::now().naive_utc()))
+    .execute(db)
+}
+
+/// Stores a one-time token, retiring any unused ones the user already has
+/// for the same purpose.
+pub fn create_one_time_token(db: &PgConnection, token: &NewOneTimeToken) -> QueryResult<()> {
+    db.transaction(|| {
+        retire_one_time_tokens(db, token.user_id, token.purpose)?;
+        diesel::insert_into(one_time_tokens::table)
+            .values(token)
+            .execute(db)?;
+        Ok(())
+    })
+}
+
+/// Uses up token `id` if it is meant for `purpose` and still live, returning
+/// the user and the email it was sent to.
+pub fn redeem_one_time_token(
+    db: &PgConnection,
+    id: &str,
+    purpose: TokenPurpose,
+) -> QueryResult<Option<(i32, String)>> {
+    let now = Utc::now().naive_utc();
+    diesel::update(
+        one_time_tokens::table
+            .filter(one_time_tokens::id.eq(id))
+            .filter(one_time_tokens::purpose.eq(purpose as i32))
+            .filter(one_time_tokens::used_at.is_null())
+            .filter(one_time_tokens::expires_at.gt(now)),
+    )
+    .set(one_time_tokens::used_at.eq(now))
+    .returning((one_time_tokens::user_id, one_time_tokens::email))
+    .get_result(db)
+    .optional()
+}
+
+pub fn create_invite(db: &PgConnection, invite: &NewInvite) -> QueryResult<Invite> {
+    diesel::insert_into(invites::table)
+        .values(invite)
+        .get_result(db)
+}
+
+/// Every invite, newest first.
+pub fn invites(db: &PgConnection) -> QueryResult<Vec<Invite>> {
+    invites::table.order(invites::created_at.desc()).load(db)
+}
+
+/// Deletes an invite that has not been used yet.
+pub fn delete_invite(db: &PgConnection, code: &str) -> QueryResult<usize> {
+    diesel::delete(
+        invites::table
+            .filter(invites::code.eq(code))
+            .filter(invites::used_at.is_null()),
+    )
+    .execute(db)
+}
+
+/// Users whose username, nickname or email contains `q`, oldest accounts
+/// first, along with how many there are in total.
+pub fn search_users(
+    db: &PgConnection,
+    q: Option<&str>,
+    start: i64,
+    count: i64,
+) -> QueryResult<(i64, Vec<User>)> {
+    let matching = || {
+        let mut query = users::table.into_boxed();
+        if let Some(q) = q {
+            let pattern = format!(
+                "%{}%",
+                q.replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            );
+            query = query.filter(
+                users::username
+                    .ilike(pattern.clone())
+                    .or(users::nickname.ilike(pattern.clone()))
+                    .or(users::email.ilike(pattern)),
+            );
+        }
+        query
+    };
+    let total = matching().count().get_result(db)?;
+    let list = matching()
+        .order(users::id.asc())
+        .offset(start)
+        .limit(count)
+        .load(db)?;
+    Ok((total, list))
+}
+
+/// Bans a user until `until`, or until lifted when `None`, and ends all of
+/// their sessions.
+pub fn ban_user(db: &PgConnection, pk: i32, until: Option<NaiveDateTime>) -> QueryResult<usize> {
+    db.transaction(|| {
+        let updated = diesel::update(users::table.find(pk))
+            .set((users::banned.eq(true), users::banned_until.eq(until)))
+            .execute(db)?;
+        revoke_user_refresh_tokens(db, pk)?;
+        Ok(updated)
+    })
+}
+
+pub fn unban_user(db: &PgConnection, pk: i32) -> QueryResult<usize> {
+    diesel::update(users::table.find(pk))
+        .set((
+            users::banned.eq(false),
+            users::banned_until.eq(None::<NaiveDateTime>),
+        ))
+        .execute(db)
+}
+
+/// Makes a user pick a new password before logging in again, and ends all
+/// of their sessions.
+pub fn require_password_reset(db: &PgConnection, pk: i32) -> QueryResult<usize> {
+    db.transaction(|| {
+        let updated = diesel::update(users::table.find(pk))
+            .set(users::password_reset_required.eq(true))
+            .execute(db)?;
+        revoke_user_refresh_tokens(db, pk)?;
+        Ok(updated)
+    })
+}
+
+/// Replaces a user's password, lifting any pending password reset, and
+/// ends every session of the user but `keep`. Unused password reset links
+/// stop working.
+pub fn set_password(
+    db: &PgConnection,
+    pk: i32,
+    pass: &str,
+    keep: Option<&str>,
+) -> QueryResult<usize> {
+    let pass_hashed = password::hash(pass).map_err(hash_error)?;
+    db.transaction(|| {
+        let updated = diesel::update(users::table.find(pk))
+            .set((
+                users::pass.eq(&pass_hashed),
+                users::password_reset_required.eq(false),
+            ))
+            .execute(db)?;
+        match keep {
+            Some(keep) => revoke_other_refresh_tokens(db, pk, keep)?,
+            None => revoke_user_refresh_tokens(db, pk)?,
+        };
+        retire_one_time_tokens(db, pk, TokenPurpose::ResetPassword as i32)?;
+        Ok(updated)
+    })
+}
+
+/// Deletes a user. Their posts go to `reassign_to` when given and are
+/// deleted otherwise; their comments and revisions stay without an author.
+pub fn delete_user(db: &PgConnection, pk: i32, reassign_to: Option<i32>) -> QueryResult<usize> {
+    db.transaction(|| {
+        let authored = posts::table.filter(posts::author.eq(pk));
+        match reassign_to {
+            Some(to) => diesel::update(authored)
+                .set(posts::author.eq(to))
+                .execute(db)?,
+            None => diesel::delete(authored).execute(db)?,
+        };
+        diesel::delete(users::table.find(pk)).execute(db)
+    })
 }
 
-pub fn by_email<'a>(email: &'a str) -> QueryResult<Vec<User>> {
-    let db = establish_connection();
+pub fn by_username(db: &PgConnection, username: &str) -> QueryResult<Vec<User>> {
     users::table
-        .filter(users::dsl::email.eq(email))
-        .load::<User>(&db)
+        .filter(users::dsl::username.eq(username))
+        .load::<User>(db)
+}
+
+/// The password hash and share token a post should have at `visibility`.
+/// `password` replaces the current hash; a share token, once made, is kept
+/// so that old links work again if the post goes back to unlisted.
+fn post_secrets(
+    visibility: i32,
+    password: Option<&str>,
+    current: Option<&Post>,
+) -> QueryResult<(Option<String>, Option<String>)> {
+    let password_hash = match password {
+        _ if visibility != Visibility::Password as i32 => None,
+        Some(pass) => Some(password::hash(pass).map_err(hash_error)?),
+        None => current.and_then(|post| post.password_hash.clone()),
+    };
+    let share_token = match current.and_then(|post| post.share_token.clone()) {
+        None if visibility == Visibility::Unlisted as i32 => Some(random_hex(16)),
+        token => token,
+    };
+    Ok((password_hash, share_token))
 }
 
+/// Inserts a post under `slug`, or under a slug made from its title when
+/// `slug` is `None`. `password` is required for password-protected posts.
 pub fn create_post<'a>(
-    title: &'a str,
-    body: &'a str,
-    author: i32,
+    db: &PgConnection,
+    new_post: &NewPost<'a>,
     tags: Vec<&'a str>,
-    permission: i32,
+    slug: Option<&'a str>,
+    password: Option<&'a str>,
 ) -> QueryResult<Post> {
-    let db = establish_connection();
-    let new_post = NewPost {
-        title,
-        body,
-        author,
-        tags: &tags.join("|"),
-        permission,
-    };
-    diesel::insert_into(posts::table)
-        .values(&new_post)
-        .get_result(&db)
+    let (password_hash, share_token) = post_secrets(new_post.visibility, password, None)?;
+    db.transaction(|| {
+        let body_html = markdown::render(new_post.body);
+        let slug = match slug {
+            Some(slug) => slugify(slug),
+            None => unique_slug(db, &slugify(new_post.title), None)?,
+        };
+        let post: Post = diesel::insert_into(posts::table)
+            .values((
+                new_post,
+                posts::body_html.eq(&body_html),
+                posts::slug.eq(&slug),
+                posts::password_hash.eq(&password_hash),
+                posts::share_token.eq(&share_token),
+            ))
+            .get_result(db)?;
+        set_post_tags(db, post.id, &tags)?;
+        add_revision(db, &post, new_post.author)?;
+        Ok(post)
+    })
 }
 
-pub fn posts_by<'a>(start: i64, count: i64) -> QueryResult<Vec<i32>> {
-    let db = establish_connection();
-    posts::table
-        .order(posts::modified_at.desc())
+/// `base`, or the first of `base-2`, `base-3`, ... that no post other than
+/// `post_id` uses or used to use.
+fn unique_slug(db: &PgConnection, base: &str, post_id: Option<i32>) -> QueryResult<String> {
+    let pattern = format!("{}%", base);
+    let mut current = posts::table
+        .filter(posts::slug.like(&pattern))
+        .select(posts::slug)
+        .into_boxed();
+    let mut former = post_slug_redirects::table
+        .filter(post_slug_redirects::slug.like(&pattern))
+        .select(post_slug_redirects::slug)
+        .into_boxed();
+    if let Some(id) = post_id {
+        current = current.filter(posts::id.ne(id));
+        former = former.filter(post_slug_redirects::post_id.ne(id));
+    }
+    let mut taken = current.load::<String>(db)?;
+    taken.extend(former.load::<String>(db)?);
+    let mut slug = base.to_string();
+    let mut n = 1;
+    while taken.contains(&slug) {
+        n += 1;
+        slug = format!("{}-{}", base, n);
+    }
+    Ok(slug)
+}
+
+/// Gives posts that still use their id as slug, as the slug migration left
+/// them, a slug made from their title. The id keeps working as a former
+/// slug. Returns the number of posts changed.
+pub fn backfill_slugs(db: &PgConnection) -> QueryResult<usize> {
+    db.transaction(|| {
+        let legacy: Vec<(i32, String, String)> = posts::table
+            .filter(sql::<Bool>("posts.slug = posts.id::TEXT"))
+            .select((posts::id, posts::title, posts::slug))
+            .for_update()
+            .load(db)?;
+        let mut changed = 0;
+        for (id, title, old) in &legacy {
+            let slug = unique_slug(db, &slugify(title), Some(*id))?;
+            if &slug == old {
+                continue;
+            }
+            changed += 1;
+            diesel::update(posts::table.find(id))
+                .set(posts::slug.eq(&slug))
+                .execute(db)?;
+            diesel::insert_into(post_slug_redirects::table)
+                .values(&SlugRedirect {
+                    slug: old,
+                    post_id: *id,
+                })
+                .on_conflict_do_nothing()
+                .execute(db)?;
+        }
+        Ok(changed)
+    })
+}
+
+/// The post that uses or used to use `slug`.
+pub fn slug_owner(db: &PgConnection, slug: &str) -> QueryResult<Option<i32>> {
+    let current = posts::table
+        .filter(posts::slug.eq(slug))
         .select(posts::id)
-        .offset(start)
+        .first::<i32>(db)
+        .optional()?;
+    match current {
+        Some(id) => Ok(Some(id)),
+        None => post_slug_redirects::table
+            .find(slug)
+            .select(post_slug_redirects::post_id)
+            .first::<i32>(db)
+            .optional(),
+    }
+}
+
+pub fn by_share_token(db: &PgConnection, token: &str) -> QueryResult<Post> {
+    posts::table.filter(posts::share_token.eq(token)).first(db)
+}
+
+pub fn by_slug(db: &PgConnection, slug: &str) -> QueryResult<Post> {
+    posts::table.filter(posts::slug.eq(slug)).first(db)
+}
+
+/// The post that went by `slug` before it was renamed.
+pub fn by_former_slug(db: &PgConnection, slug: &str) -> QueryResult<Post> {
+    post_slug_redirects::table
+        .inner_join(posts::table)
+        .filter(post_slug_redirects::slug.eq(slug))
+        .select(posts::all_columns)
+        .first(db)
+}
+
+/// Replaces the tags of a post, creating any tag that does not exist yet.
+fn set_post_tags(db: &PgConnection, post_id: i32, tags: &[&str]) -> QueryResult<()> {
+    diesel::delete(post_tags::table.filter(post_tags::post_id.eq(post_id))).execute(db)?;
+    let mut names: Vec<&str> = tags
+        .iter()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    if names.is_empty() {
+        return Ok(());
+    }
+    let new_tags: Vec<NewTag> = names.iter().map(|&name| NewTag { name }).collect();
+    diesel::insert_into(tags::table)
+        .values(&new_tags)
+        .on_conflict(tags::name)
+        .do_nothing()
+        .execute(db)?;
+    let rows: Vec<PostTag> = tags::table
+        .filter(tags::name.eq_any(&names))
+        .select(tags::id)
+        .load::<i32>(db)?
+        .into_iter()
+        .map(|tag_id| PostTag { post_id, tag_id })
+        .collect();
+    diesel::insert_into(post_tags::table)
+        .values(&rows)
+        .execute(db)?;
+    Ok(())
+}
+
+/// Turns listing rows into headers, with their tags, excerpt and reading
+/// time.
+fn post_headers(db: &PgConnection, rows: Vec<PostHeaderRow>) -> QueryResult<Vec<PostHeader>> {
+    let ids: Vec<i32> = rows.iter().map(|row| row.id).collect();
+    let mut tags_by_post: HashMap<i32, Vec<String>> = HashMap::new();
+    for (id, name) in tags_of_posts(db, &ids)? {
+        tags_by_post.entry(id).or_default().push(name);
+    }
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let text = markdown::plain_text(&row.body);
+            let visibility = Visibility::from_i32(row.visibility);
+            PostHeader {
+                id: row.id,
+                slug: row.slug,
+                title: row.title,
+                author: row.author,
+                author_nickname: row.author_nickname,
+                tags: tags_by_post.remove(&row.id).unwrap_or_default(),
+                excerpt: (visibility != Visibility::Password)
+                    .then(|| markdown::truncate(&text, CONFIG.blog.excerpt_length)),
+                reading_time: markdown::reading_time(&text, CONFIG.blog.words_per_minute),
+                visibility,
+                created_at: row.created_at,
+                modified_at: row.modified_at,
+                published_at: row.published_at,
+            }
+        })
+        .collect())
+}
+
+pub fn tags_of(db: &PgConnection, post_id: i32) -> QueryResult<Vec<String>> {
+    post_tags::table
+        .inner_join(tags::table)
+        .filter(post_tags::post_id.eq(post_id))
+        .order(tags::name.asc())
+        .select(tags::name)
+        .load::<String>(db)
+}
+
+/// Who a listing is for. Logged-in readers also see members-only posts and
+/// the private posts they wrote; editors see every private post.
+#[derive(Clone, Copy, Default)]
+pub struct Reader {
+    pub id: Option<i32>,
+    /// Whether the reader may edit any post.
+    pub editor: bool,
+}
+
+/// Visibilities of every post listed to `reader`, on top of their own
+/// private posts. Password-protected posts are listed by their header only.
+fn listed(reader: Reader) -> Vec<i32> {
+    let mut visibilities = vec![Visibility::Public as i32, Visibility::Password as i32];
+    if reader.id.is_some() {
+        visibilities.push(Visibility::Members as i32);
+    }
+    if reader.editor {
+        visibilities.push(Visibility::Private as i32);
+    }
+    visibilities
+}
+
+/// Every tag attached to at least one listed post, with the number of
+/// listed posts using it.
+pub fn tag_counts(db: &PgConnection, reader: Reader) -> QueryResult<Vec<TagCount>> {
+    tags::table
+        .inner_join(post_tags::table.inner_join(posts::table))
+        .filter(posts::status.eq(PostStatus::Published as i32))
+        .filter(
+            posts::visibility
+                .eq_any(listed(reader))
+                .or(posts::visibility
+                    .eq(Visibility::Private as i32)
+                    .and(posts::author.nullable().eq(reader.id))),
+        )
+        .group_by(tags::id)
+        .order(tags::name.asc())
+        // diesel 1.x cannot mix aggregates with plain columns in `select`.
+        .select((tags::name, sql::<BigInt>("COUNT(*)")))
+        .load::<TagCount>(db)
+}
+
+/// The most recently published public posts with their author's nickname, optionally limited
+/// to posts tagged `tag`.
+pub fn feed_posts(
+    db: &PgConnection,
+    tag: Option<&str>,
+    count: i64,
+) -> QueryResult<Vec<(Post, String)>> {
+    let mut query = posts::table
+        .inner_join(users::table)
+        .filter(posts::visibility.eq(Visibility::Public as i32))
+        .filter(posts::status.eq(PostStatus::Published as i32))
+        .order(posts::published_at.desc())
+        .select((posts::all_columns, users::nickname))
         .limit(count)
-        .load::<i32>(&db)
+        .into_boxed();
+    if let Some(tag) = tag {
+        query = query.filter(
+            posts::id.eq_any(
+                post_tags::table
+                    .inner_join(tags::table)
+                    .filter(tags::name.eq(tag))
+                    .select(post_tags::post_id),
+            ),
+        );
+    }
+    query.load::<(Post, String)>(db)
 }
 
-pub fn post_header_by<'a>(start: i64, count: i64) -> QueryResult<Vec<PostHeader>> {
-    let db = establish_connection();
-    posts::table
-        .order(posts::modified_at.desc())
+/// `(post id, tag name)` pairs for every tag of the given posts.
+pub fn tags_of_posts(db: &PgConnection, post_ids: &[i32]) -> QueryResult<Vec<(i32, String)>> {
+    post_tags::table
+        .inner_join(tags::table)
+        .filter(post_tags::post_id.eq_any(post_ids))
+        .order((post_tags::post_id, tags::name.asc()))
+        .select((post_tags::post_id, tags::name))
+        .load::<(i32, String)>(db)
+}
+
+/// Optional filters for `search_posts`; `None` means "any".
+pub struct SearchFilter {
+    pub tag: Option<String>,
+    pub author: Option<i32>,
+    /// Only posts published from this day on, in `blog.timezone`.
+    pub from: Option<NaiveDate>,
+    /// Only posts published up to and including this day, in `blog.timezone`.
+    pub to: Option<NaiveDate>,
+}
+
+// `posts.search_vector` is maintained by a trigger and left out of `schema.rs`
+// since diesel 1.x has no `tsvector` type, so search goes through raw SQL.
+const SEARCH_WHERE: &str = "
+    FROM posts JOIN users ON users.id = posts.author,
+         websearch_to_tsquery('simple', $1) AS query,
+         LATERAL (SELECT CASE WHEN posts.visibility = $4
+                              THEN ts_filter(posts.search_vector, '{a,b}')
+                              ELSE posts.search_vector END AS vector) AS searched
+    WHERE searched.vector @@ query
+      AND posts.status = $2
+      AND (posts.visibility = ANY($3) OR (posts.visibility = $5 AND posts.author = $6))
+      AND ($7::VARCHAR IS NULL OR EXISTS (
+          SELECT 1 FROM post_tags JOIN tags ON tags.id = post_tags.tag_id
+          WHERE post_tags.post_id = posts.id AND tags.name = $7))
+      AND ($8::INT IS NULL OR posts.author = $8)
+      AND ($9::DATE IS NULL
+           OR posts.published_at >= ($9::TIMESTAMP AT TIME ZONE $11) AT TIME ZONE 'UTC')
+      AND ($10::DATE IS NULL
+           OR posts.published_at < (($10 + 1)::TIMESTAMP AT TIME ZONE $11) AT TIME ZONE 'UTC')";
+
+/// Ranked full-text search over the posts a reader may list, with the total
+/// number of matches. Like in listings, only the header of a
+/// password-protected post is searched and it gets no snippet.
+pub fn search_posts(
+    db: &PgConnection,
+    text: &str,
+    reader: Reader,
+    filter: &SearchFilter,
+    start: i64,
+    count: i64,
+) -> QueryResult<(i64, Vec<SearchHit>)> {
+    let visibilities = listed(reader);
+    let total = diesel::sql_query(format!("SELECT COUNT(*) AS count {}", SEARCH_WHERE))
+        .bind::<Text, _>(text)
+        .bind::<Integer, _>(PostStatus::Published as i32)
+        .bind::<Array<Integer>, _>(&visibilities)
+        .bind::<Integer, _>(Visibility::Password as i32)
+        .bind::<Integer, _>(Visibility::Private as i32)
+        .bind::<Nullable<Integer>, _>(reader.id)
+        .bind::<Nullable<Text>, _>(filter.tag.as_deref())
+        .bind::<Nullable<Integer>, _>(filter.author)
+        .bind::<Nullable<Date>, _>(filter.from)
+        .bind::<Nullable<Date>, _>(filter.to)
+        .bind::<Text, _>(&CONFIG.blog.timezone)
+        .get_result::<Count>(db)?
+        .count;
+    let rows = diesel::sql_query(format!(
+        "SELECT posts.id, posts.slug, posts.title, posts.author,
+                users.nickname AS author_nickname, posts.body, posts.visibility,
+                posts.created_at, posts.modified_at, posts.published_at,
+                ts_rank(searched.vector, query) AS rank,
+                ts_rank(searched.vector, query)::VARCHAR AS sort_key,
+                CASE WHEN posts.visibility = $4 THEN '' ELSE ts_headline('simple',
+                    replace(replace(replace(posts.body, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'),
+                    query,
+                    'StartSel=<mark>, StopSel=</mark>, MaxFragments=2, MaxWords=30, MinWords=10'
+                ) END AS snippet
+         {}
+         ORDER BY rank DESC, posts.modified_at DESC
+         OFFSET $12 LIMIT $13",
+        SEARCH_WHERE
+    ))
+    .bind::<Text, _>(text)
+    .bind::<Integer, _>(PostStatus::Published as i32)
+    .bind::<Array<Integer>, _>(&visibilities)
+    .bind::<Integer, _>(Visibility::Password as i32)
+    .bind::<Integer, _>(Visibility::Private as i32)
+    .bind::<Nullable<Integer>, _>(reader.id)
+    .bind::<Nullable<Text>, _>(filter.tag.as_deref())
+    .bind::<Nullable<Integer>, _>(filter.author)
+    .bind::<Nullable<Date>, _>(filter.from)
+    .bind::<Nullable<Date>, _>(filter.to)
+    .bind::<Text, _>(&CONFIG.blog.timezone)
+    .bind::<BigInt, _>(start)
+    .bind::<BigInt, _>(count)
+    .load::<SearchHitRow>(db)?;
+    let (posts, matches): (Vec<_>, Vec<_>) = rows
+        .into_iter()
+        .map(|row| (row.post, (row.rank, row.snippet)))
+        .unzip();
+    let hits = post_headers(db, posts)?
+        .into_iter()
+        .zip(matches)
+        .map(|(post, (rank, snippet))| SearchHit {
+            post,
+            rank,
+            snippet,
+        })
+        .collect();
+    Ok((total, hits))
+}
+
+/// A position in a post listing: the sort key and id of the post a page
+/// starts after, or with `before`, the post it ends before.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub sort: PostSort,
+    pub before: bool,
+    pub key: String,
+    pub id: i32,
+}
+
+impl Cursor {
+    /// Hex-encoded, so that clients treat it as opaque and it needs no
+    /// escaping in a query string.
+    pub fn encode(&self) -> String {
+        let direction = if self.before { "b" } else { "a" };
+        hex::encode(format!(
+            "{}:{}:{}:{}",
+            self.sort as i32, direction, self.id, self.key
+        ))
+    }
+
+    pub fn decode(cursor: &str) -> Option<Cursor> {
+        let raw = String::from_utf8(hex::decode(cursor).ok()?).ok()?;
+        let mut parts = raw.splitn(4, ':');
+        let sort = PostSort::from_i32(parts.next()?.parse().ok()?)?;
+        let before = match parts.next()? {
+            "a" => false,
+            "b" => true,
+            _ => return None,
+        };
+        let id = parts.next()?.parse().ok()?;
+        let key = parts.next()?.to_string();
+        Some(Cursor {
+            sort,
+            before,
+            key,
+            id,
+        })
+    }
+}
+
+/// Which posts a listing shows, and which page of them.
+pub struct PostListing<'a> {
+    pub reader: Reader,
+    pub tag: Option<&'a str>,
+    pub author: Option<i32>,
+    /// Only posts published from this day on, in `blog.timezone`.
+    pub from: Option<NaiveDate>,
+    /// Only posts published before this day, in `blog.timezone`.
+    pub to: Option<NaiveDate>,
+    pub sort: PostSort,
+    pub cursor: Option<&'a Cursor>,
+    pub count: i64,
+}
+
+/// A page of a listing with the cursors of the pages around it and the
+/// number of posts in the whole listing.
+pub struct PostPage {
+    pub total: i64,
+    pub posts: Vec<PostHeader>,
+    pub next: Option<Cursor>,
+    pub prev: Option<Cursor>,
+}
+
+// Keyset pagination needs the sort column picked at runtime, which diesel
+// 1.x cannot express, so listings go through raw SQL like search.
+const LISTING_WHERE: &str = "
+    FROM posts JOIN users ON users.id = posts.author
+    WHERE posts.status = $1
+      AND (posts.visibility = ANY($2) OR (posts.visibility = $3 AND posts.author = $4))
+      AND ($5::VARCHAR IS NULL OR EXISTS (
+          SELECT 1 FROM post_tags JOIN tags ON tags.id = post_tags.tag_id
+          WHERE post_tags.post_id = posts.id AND tags.name = $5))
+      AND ($6::INT IS NULL OR posts.author = $6)
+      AND ($7::DATE IS NULL
+           OR posts.published_at >= ($7::TIMESTAMP AT TIME ZONE $9) AT TIME ZONE 'UTC')
+      AND ($8::DATE IS NULL
+           OR posts.published_at < ($8::TIMESTAMP AT TIME ZONE $9) AT TIME ZONE 'UTC')";
+
+/// The expression a listing is sorted by, the type a cursor key is cast
+/// back to, and whether the highest value comes first.
+fn sort_key(sort: PostSort) -> (&'static str, &'static str, bool) {
+    match sort {
+        PostSort::Created => ("posts.created_at", "TIMESTAMP", true),
+        PostSort::Modified => ("posts.modified_at", "TIMESTAMP", true),
+        PostSort::Published => ("posts.published_at", "TIMESTAMP", true),
+        PostSort::Title => ("posts.title", "VARCHAR", false),
+        PostSort::Popularity => (
+            "(SELECT COUNT(*) FROM comments
+              WHERE comments.post_id = posts.id AND comments.status = 1)",
+            "BIGINT",
+            true,
+        ),
+    }
+}
+
+/// One page of published posts the reader may list, ordered by
+/// `listing.sort` with the id breaking ties.
+pub fn list_posts(db: &PgConnection, listing: &PostListing) -> QueryResult<PostPage> {
+    let visibilities = listed(listing.reader);
+    let total = diesel::sql_query(format!("SELECT COUNT(*) AS count {}", LISTING_WHERE))
+        .bind::<Integer, _>(PostStatus::Published as i32)
+        .bind::<Array<Integer>, _>(&visibilities)
+        .bind::<Integer, _>(Visibility::Private as i32)
+        .bind::<Nullable<Integer>, _>(listing.reader.id)
+        .bind::<Nullable<Text>, _>(listing.tag)
+        .bind::<Nullable<Integer>, _>(listing.author)
+        .bind::<Nullable<Date>, _>(listing.from)
+        .bind::<Nullable<Date>, _>(listing.to)
+        .bind::<Text, _>(&CONFIG.blog.timezone)
+        .get_result::<Count>(db)?
+        .count;
+    let (key, cast, descending) = sort_key(listing.sort);
+    // A page before the cursor is read in reverse order and flipped back.
+    let backwards = listing.cursor.is_some_and(|cursor| cursor.before);
+    let (op, order) = if descending != backwards {
+        ("<", "DESC")
+    } else {
+        (">", "ASC")
+    };
+    let mut rows = diesel::sql_query(format!(
+        "SELECT posts.id, posts.slug, posts.title, posts.author,
+                users.nickname AS author_nickname, posts.body, posts.visibility,
+                posts.created_at, posts.modified_at, posts.published_at,
+                ({key})::VARCHAR AS sort_key
+         {filter}
+           AND ($10::VARCHAR IS NULL OR ({key}, posts.id) {op} ($10::{cast}, $11))
+         ORDER BY {key} {order}, posts.id {order}
+         LIMIT $12",
+        key = key,
+        filter = LISTING_WHERE,
+        op = op,
+        cast = cast,
+        order = order,
+    ))
+    .bind::<Integer, _>(PostStatus::Published as i32)
+    .bind::<Array<Integer>, _>(&visibilities)
+    .bind::<Integer, _>(Visibility::Private as i32)
+    .bind::<Nullable<Integer>, _>(listing.reader.id)
+    .bind::<Nullable<Text>, _>(listing.tag)
+    .bind::<Nullable<Integer>, _>(listing.author)
+    .bind::<Nullable<Date>, _>(listing.from)
+    .bind::<Nullable<Date>, _>(listing.to)
+    .bind::<Text, _>(&CONFIG.blog.timezone)
+    .bind::<Nullable<Text>, _>(listing.cursor.map(|cursor| cursor.key.as_str()))
+    .bind::<Nullable<Integer>, _>(listing.cursor.map(|cursor| cursor.id))
+    .bind::<BigInt, _>(listing.count + 1)
+    .load::<PostHeaderRow>(db)?;
+    let more = rows.len() as i64 > listing.count;
+    rows.truncate(listing.count as usize);
+    if backwards {
+        rows.reverse();
+    }
+    let (has_prev, has_next) = if backwards {
+        (more, true)
+    } else {
+        (listing.cursor.is_some(), more)
+    };
+    let cursor = |row: &PostHeaderRow, before| Cursor {
+        sort: listing.sort,
+        before,
+        key: row.sort_key.clone(),
+        id: row.id,
+    };
+    let next = rows
+        .last()
+        .filter(|_| has_next)
+        .map(|row| cursor(row, false));
+    let prev = rows
+        .first()
+        .filter(|_| has_prev)
+        .map(|row| cursor(row, true));
+    Ok(PostPage {
+        total,
+        posts: post_headers(db, rows)?,
+        next,
+        prev,
+    })
+}
+
+/// Fails unless Postgres knows the time zone `zone`.
+pub fn check_timezone(db: &PgConnection, zone: &str) -> QueryResult<()> {
+    diesel::sql_query("SELECT now() AT TIME ZONE $1")
+        .bind::<Text, _>(zone)
+        .execute(db)
+        .map(|_| ())
+}
+
+/// How many posts a reader may list were published in each month, newest
+/// month first. Months are those of `blog.timezone`.
+pub fn archive_months(db: &PgConnection, reader: Reader) -> QueryResult<Vec<ArchiveMonth>> {
+    diesel::sql_query(
+        "SELECT EXTRACT(YEAR FROM local)::INT AS year, EXTRACT(MONTH FROM local)::INT AS month,
+                COUNT(*) AS count
+         FROM (SELECT (posts.published_at AT TIME ZONE 'UTC') AT TIME ZONE $5 AS local
+               FROM posts
+               WHERE posts.status = $1
+                 AND (posts.visibility = ANY($2)
+                      OR (posts.visibility = $3 AND posts.author = $4))) AS published
+         GROUP BY 1, 2
+         ORDER BY 1 DESC, 2 DESC",
+    )
+    .bind::<Integer, _>(PostStatus::Published as i32)
+    .bind::<Array<Integer>, _>(listed(reader))
+    .bind::<Integer, _>(Visibility::Private as i32)
+    .bind::<Nullable<Integer>, _>(reader.id)
+    .bind::<Text, _>(&CONFIG.blog.timezone)
+    .load(db)
+}
+
+/// Renames the tag `from` to `to`. If `to` already exists, the two tags are
+/// merged. Returns `false` when `from` does not exist.
+pub fn rename_tag(db: &PgConnection, from: &str, to: &str) -> QueryResult<bool> {
+    db.transaction(|| {
+        let source = match tags::table
+            .filter(tags::name.eq(from))
+            .select(tags::id)
+            .first::<i32>(db)
+            .optional()?
+        {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        let target = tags::table
+            .filter(tags::name.eq(to))
+            .select(tags::id)
+            .first::<i32>(db)
+            .optional()?;
+        match target {
+            Some(target) if target == source => {}
+            Some(target) => {
+                let rows: Vec<PostTag> = post_tags::table
+                    .filter(post_tags::tag_id.eq(source))
+                    .select(post_tags::post_id)
+                    .load::<i32>(db)?
+                    .into_iter()
+                    .map(|post_id| PostTag {
+                        post_id,
+                        tag_id: target,
+                    })
+                    .collect();
+                diesel::insert_into(post_tags::table)
+                    .values(&rows)
+                    .on_conflict_do_nothing()
+                    .execute(db)?;
+                diesel::delete(tags::table.find(source)).execute(db)?;
+            }
+            None => {
+                diesel::update(tags::table.find(source))
+                    .set(tags::name.eq(to))
+                    .execute(db)?;
+            }
+        }
+        Ok(true)
+    })
+}
+
+/// The number of posts a reader may list, optionally only those written by
+/// `author`.
+pub fn count_posts(db: &PgConnection, reader: Reader, author: Option<i32>) -> QueryResult<i64> {
+    let mut query = posts::table
+        .filter(posts::status.eq(PostStatus::Published as i32))
+        .filter(
+            posts::visibility
+                .eq_any(listed(reader))
+                .or(posts::visibility
+                    .eq(Visibility::Private as i32)
+                    .and(posts::author.nullable().eq(reader.id))),
+        )
+        .into_boxed();
+    if let Some(author) = author {
+        query = query.filter(posts::author.eq(author));
+    }
+    query.count().get_result(db)
+}
+
+pub fn by_post_id(db: &PgConnection, pk: i32) -> QueryResult<Post> {
+    posts::table.find(pk).first(db)
+}
+
+pub fn delete_post(db: &PgConnection, pk: i32) -> QueryResult<usize> {
+    diesel::delete(posts::table.filter(posts::id.eq(pk))).execute(db)
+}
+
+/// New content for a post.
+pub struct PostEdit<'a> {
+    pub title: &'a str,
+    pub body: &'a str,
+    pub tags: Vec<&'a str>,
+    /// A slug picked by the author. When `None`, a new slug is made from
+    /// the title if it changed.
+    pub slug: Option<&'a str>,
+    /// `None` keeps the current visibility.
+    pub visibility: Option<Visibility>,
+    /// A new password for a password-protected post.
+    pub password: Option<&'a str>,
+}
+
+/// Saves new content for a post and records it as a new revision by
+/// `editor`, provided the post is still at revision `expected`. Returns
+/// `None` without writing anything when it is not. A replaced slug keeps
+/// pointing at the post.
+pub fn edit_post(
+    db: &PgConnection,
+    pk: i32,
+    edit: &PostEdit,
+    editor: i32,
+    expected: i32,
+) -> QueryResult<Option<Post>> {
+    let body_html = markdown::render(edit.body);
+    db.transaction(|| {
+        let previous: Post = match posts::table
+            .find(pk)
+            .filter(posts::revision.eq(expected))
+            .for_update()
+            .first(db)
+            .optional()?
+        {
+            Some(post) => post,
+            None => return Ok(None),
+        };
+        let slug = match edit.slug {
+            Some(slug) => slugify(slug),
+            None if edit.title != previous.title => {
+                unique_slug(db, &slugify(edit.title), Some(pk))?
+            }
+            None => previous.slug.clone(),
+        };
+        let visibility = edit
+            .visibility
+            .map_or(previous.visibility, |visibility| visibility as i32);
+        let (password_hash, share_token) =
+            post_secrets(visibility, edit.password, Some(&previous))?;
+        if slug != previous.slug {
+            diesel::delete(post_slug_redirects::table.find(&slug)).execute(db)?;
+            diesel::insert_into(post_slug_redirects::table)
+                .values(&SlugRedirect {
+                    slug: &previous.slug,
+                    post_id: pk,
+                })
+                .execute(db)?;
+        }
+        let post: Post = diesel::update(posts::table.find(pk))
+            .set((
+                posts::title.eq(edit.title),
+                posts::body.eq(edit.body),
+                posts::body_html.eq(&body_html),
+                posts::slug.eq(&slug),
+                posts::visibility.eq(visibility),
+                posts::password_hash.eq(&password_hash),
+                posts::share_token.eq(&share_token),
+                posts::modified_at.eq(diesel::dsl::now),
+                posts::revision.eq(posts::revision + 1),
+            ))
+            .get_result(db)?;
+        set_post_tags(db, pk, &edit.tags)?;
+        add_revision(db, &post, editor)?;
+        Ok(Some(post))
+    })
+}
+
+/// Records the current title, body and tags of `post` as revision
+/// `post.revision`. Must run in the transaction that saved them.
+fn add_revision(db: &PgConnection, post: &Post, editor: i32) -> QueryResult<()> {
+    let tags = tags_of(db, post.id)?;
+    diesel::insert_into(post_revisions::table)
+        .values(&NewPostRevision {
+            post_id: post.id,
+            revision: post.revision,
+            title: &post.title,
+            body: &post.body,
+            tags: &tags,
+            editor: Some(editor),
+        })
+        .execute(db)?;
+    Ok(())
+}
+
+/// Every revision of a post, newest first.
+pub fn revisions_of(db: &PgConnection, post_id: i32) -> QueryResult<Vec<RevisionHeader>> {
+    post_revisions::table
+        .filter(post_revisions::post_id.eq(post_id))
+        .order(post_revisions::revision.desc())
         .select((
-            posts::id,
-            posts::title,
-            posts::author,
-            posts::created_at,
-            posts::modified_at,
+            post_revisions::revision,
+            post_revisions::title,
+            post_revisions::editor,
+            post_revisions::created_at,
         ))
-        .offset(start)
-        .limit(count)
-        .load::<PostHeader>(&db)
+        .load::<RevisionHeader>(db)
 }
 
-pub fn count_posts() -> QueryResult<i64> {
-    let db = establish_connection();
-    posts::table.count().get_result(&db)
+pub fn find_revision(db: &PgConnection, post_id: i32, revision: i32) -> QueryResult<PostRevision> {
+    post_revisions::table
+        .filter(post_revisions::post_id.eq(post_id))
+        .filter(post_revisions::revision.eq(revision))
+        .select((
+            post_revisions::revision,
+            post_revisions::title,
+            post_revisions::body,
+            post_revisions::tags,
+            post_revisions::editor,
+            post_revisions::created_at,
+        ))
+        .first(db)
 }
 
-pub fn count_users() -> QueryResult<i64> {
-    let db = establish_connection();
-    users::table.count().get_result(&db)
+/// Drafts and scheduled posts of `author`, most recently edited first.
+pub fn unpublished_posts_of(db: &PgConnection, author: i32) -> QueryResult<Vec<Post>> {
+    posts::table
+        .filter(posts::author.eq(author))
+        .filter(posts::status.eq_any(&[PostStatus::Draft as i32, PostStatus::Scheduled as i32]))
+        .order(posts::modified_at.desc())
+        .load::<Post>(db)
 }
 
-pub fn by_post_id<'a>(pk: i32) -> QueryResult<Post> {
-    let db = establish_connection();
-    posts::table.find(pk).first(&db)
+pub fn set_post_status(
+    db: &PgConnection,
+    pk: i32,
+    status: PostStatus,
+    published_at: Option<NaiveDateTime>,
+) -> QueryResult<usize> {
+    diesel::update(posts::table.filter(posts::id.eq(pk)))
+        .set((
+            posts::status.eq(status as i32),
+            posts::published_at.eq(published_at),
+            posts::modified_at.eq(diesel::dsl::now),
+        ))
+        .execute(db)
 }
 
-pub fn delete_post<'a>(pk: i32) -> QueryResult<usize> {
-    let db = establish_connection();
-    diesel::delete(posts::table.filter(posts::id.eq(pk))).execute(&db)
+/// Publishes every scheduled post whose time has come.
+pub fn publish_due_posts(db: &PgConnection) -> QueryResult<usize> {
+    diesel::update(
+        posts::table
+            .filter(posts::status.eq(PostStatus::Scheduled as i32))
+            .filter(posts::published_at.le(Utc::now().naive_utc())),
+    )
+    .set((
+        posts::status.eq(PostStatus::Published as i32),
+        posts::modified_at.eq(diesel::dsl::now),
+    ))
+    .execute(db)
 }
 
-pub fn edit_post<'a>(pk: i32, title: &'a str, body: &'a str, tags: &'a str) -> QueryResult<Post> {
-    let db = establish_connection();
+pub fn set_comments_closed(db: &PgConnection, pk: i32, closed: bool) -> QueryResult<usize> {
     diesel::update(posts::table.filter(posts::id.eq(pk)))
+        .set(posts::comments_closed.eq(closed))
+        .execute(db)
+}
+
+pub fn create_comment(db: &PgConnection, comment: &NewComment) -> QueryResult<Comment> {
+    diesel::insert_into(comments::table)
+        .values(comment)
+        .get_result(db)
+}
+
+pub fn find_comment(db: &PgConnection, pk: i32) -> QueryResult<Comment> {
+    comments::table.find(pk).first(db)
+}
+
+/// Every comment of a post in `statuses`, oldest first, with the nickname of
+/// its author when it was not posted anonymously.
+pub fn comments_of_post(
+    db: &PgConnection,
+    post_id: i32,
+    statuses: &[i32],
+) -> QueryResult<Vec<(Comment, Option<String>)>> {
+    comments::table
+        .left_join(users::table)
+        .filter(comments::post_id.eq(post_id))
+        .filter(comments::status.eq_any(statuses))
+        .order((comments::created_at.asc(), comments::id.asc()))
+        .select((comments::all_columns, users::nickname.nullable()))
+        .load::<(Comment, Option<String>)>(db)
+}
+
+pub fn count_comments_by_status(db: &PgConnection, status: CommentStatus) -> QueryResult<i64> {
+    comments::table
+        .filter(comments::status.eq(status as i32))
+        .count()
+        .get_result(db)
+}
+
+/// Comments across all posts in `status`, oldest first.
+pub fn comments_by_status(
+    db: &PgConnection,
+    status: CommentStatus,
+    start: i64,
+    count: i64,
+) -> QueryResult<Vec<(Comment, Option<String>)>> {
+    comments::table
+        .left_join(users::table)
+        .filter(comments::status.eq(status as i32))
+        .order((comments::created_at.asc(), comments::id.asc()))
+        .select((comments::all_columns, users::nickname.nullable()))
+        .offset(start)
+        .limit(count)
+        .load::<(Comment, Option<String>)>(db)
+}
+
+pub fn edit_comment(
+    db: &PgConnection,
+    pk: i32,
+    body: &str,
+    status: CommentStatus,
+) -> QueryResult<usize> {
+    diesel::update(comments::table.filter(comments::id.eq(pk)))
         .set((
-            posts::title.eq(title),
-            posts::body.eq(body),
-            posts::tags.eq(tags),
+            comments::body.eq(body),
+            comments::status.eq(status as i32),
+            comments::modified_at.eq(diesel::dsl::now),
         ))
-        .get_result(&db)
+        .execute(db)
+}
+
+pub fn set_comment_status(
+    db: &PgConnection,
+    ids: &[i32],
+    status: CommentStatus,
+) -> QueryResult<usize> {
+    diesel::update(comments::table.filter(comments::id.eq_any(ids)))
+        .set(comments::status.eq(status as i32))
+        .execute(db)
+}
+
+const REFRESH_TOKEN_COLUMNS: (
+    refresh_tokens::id,
+    refresh_tokens::user_id,
+    refresh_tokens::token_hash,
+    refresh_tokens::expires_at,
+    refresh_tokens::revoked_at,
+) = (
+    refresh_tokens::id,
+    refresh_tokens::user_id,
+    refresh_tokens::token_hash,
+    refresh_tokens::expires_at,
+    refresh_tokens::revoked_at,
+);
+
+pub fn create_refresh_token<'a>(
+    db: &PgConnection,
+    id: &'a str,
+    user_id: i32,
+    token_hash: &'a str,
+    expires_at: NaiveDateTime,
+) -> QueryResult<usize> {
+    let new_token = NewRefreshToken {
+        id,
+        user_id,
+        token_hash,
+        created_at: Utc::now().naive_utc(),
+        expires_at,
+    };
+    diesel::insert_into(refresh_tokens::table)
+        .values(&new_token)
+        .execute(db)
+}
+
+/// Swaps the stored hash of session `id` from `presented_hash` to `new_hash`.
+/// Returns `None` if the session is unknown, expired or revoked. Presenting a
+/// hash that was already rotated away revokes the whole session, since the
+/// old token must have leaked.
+pub fn rotate_refresh_token<'a>(
+    db: &PgConnection,
+    id: &'a str,
+    presented_hash: &'a str,
+    new_hash: &'a str,
+    expires_at: NaiveDateTime,
+) -> QueryResult<Option<RefreshToken>> {
+    db.transaction(|| {
+        let token = match refresh_tokens::table
+            .find(id)
+            .select(REFRESH_TOKEN_COLUMNS)
+            .for_update()
+            .first::<RefreshToken>(db)
+            .optional()?
+        {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        if token.revoked_at.is_some() || token.expires_at <= Utc::now().naive_utc() {
+            return Ok(None);
+        }
+        if token.token_hash != presented_hash {
+            revoke_refresh_token(db, &token.id)?;
+            return Ok(None);
+        }
+        diesel::update(refresh_tokens::table.find(&token.id))
+            .set((
+                refresh_tokens::token_hash.eq(new_hash),
+                refresh_tokens::expires_at.eq(expires_at),
+            ))
+            .returning(REFRESH_TOKEN_COLUMNS)
+            .get_result(db)
+            .map(Some)
+    })
+}
+
+pub fn revoke_refresh_token(db: &PgConnection, id: &str) -> QueryResult<usize> {
+    diesel::update(
+        refresh_tokens::table
+            .find(id)
+            .filter(refresh_tokens::revoked_at.is_null()),
+    )
+    .set(refresh_tokens::revoked_at.eq(Utc::now().naive_utc()))
+    .execute(db)
+}
+
+/// Revokes every session of `user_id` except `keep`.
+pub fn revoke_other_refresh_tokens(
+    db: &PgConnection,
+    user_id: i32,
+    keep: &str,
+) -> QueryResult<usize> {
+    diesel::update(
+        refresh_tokens::table
+            .filter(refresh_tokens::user_id.eq(user_id))
+            .filter(refresh_tokens::id.ne(keep))
+            .filter(refresh_tokens::revoked_at.is_null()),
+    )
+    .set(refresh_tokens::revoked_at.eq(Utc::now().naive_utc()))
+    .execute(db)
+}
+
+pub fn revoke_user_refresh_tokens(db: &PgConnection, user_id: i32) -> QueryResult<usize> {
+    diesel::update(
+        refresh_tokens::table
+            .filter(refresh_tokens::user_id.eq(user_id))
+            .filter(refresh_tokens::revoked_at.is_null()),
+    )
+    .set(refresh_tokens::revoked_at.eq(Utc::now().naive_utc()))
+    .execute(db)
+}
+
+/// The owner of session `id`, if it belongs to `user_id` and is still live.
+pub fn session_user(db: &PgConnection, id: &str, user_id: i32) -> QueryResult<Option<User>> {
+    refresh_tokens::table
+        .inner_join(users::table)
+        .filter(refresh_tokens::id.eq(id))
+        .filter(refresh_tokens::user_id.eq(user_id))
+        .filter(refresh_tokens::revoked_at.is_null())
+        .filter(refresh_tokens::expires_at.gt(Utc::now().naive_utc()))
+        .select(users::all_columns)
+        .first::<User>(db)
+        .optional()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor {
+            sort: PostSort::Title,
+            before: true,
+            key: String::from("a: b:c"),
+            id: 42,
+        };
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+    }
+
+    #[test]
+    fn cursor_rejects_garbage() {
+        assert_eq!(Cursor::decode(""), None);
+        assert_eq!(Cursor::decode("not hex"), None);
+        assert_eq!(Cursor::decode(&hex::encode("9:a:1:key")), None);
+        assert_eq!(Cursor::decode(&hex::encode("0:x:1:key")), None);
+        assert_eq!(Cursor::decode(&hex::encode("0:a:id:key")), None);
+        assert_eq!(Cursor::decode(&hex::encode("0:a:1")), None);
+    }
 }
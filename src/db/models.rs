@@ -1,12 +1,77 @@
 use crate::db::schema::*;
 use chrono::prelude::*;
-use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Float4, Int4, Nullable, Text, Timestamp};
 use serde::{Deserialize, Serialize};
 
+/// The role of an account, stored in `users.permission`.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum AccountLevel {
+    /// A reader. New accounts start here.
     Default = 0,
     Admin = 1,
+    Editor = 2,
+    Author = 3,
+    Moderator = 4,
+}
+
+/// Something an account may be allowed to do beyond reading and commenting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// Write posts and manage one's own.
+    WritePosts,
+    /// Edit, restore and change the status of anyone's posts, drafts
+    /// included.
+    EditAnyPost,
+    DeleteAnyPost,
+    ManageTags,
+    /// Approve, reject and delete anyone's comments, and close posts for
+    /// comments.
+    ModerateComments,
+    /// Change the level of other accounts.
+    ManageUsers,
+}
+
+impl AccountLevel {
+    pub const ALL: [AccountLevel; 5] = [
+        AccountLevel::Default,
+        AccountLevel::Admin,
+        AccountLevel::Editor,
+        AccountLevel::Author,
+        AccountLevel::Moderator,
+    ];
+
+    pub fn from_i32(level: i32) -> Self {
+        match level {
+            1 => AccountLevel::Admin,
+            2 => AccountLevel::Editor,
+            3 => AccountLevel::Author,
+            4 => AccountLevel::Moderator,
+            _ => AccountLevel::Default,
+        }
+    }
+
+    pub fn capabilities(self) -> &'static [Capability] {
+        use Capability::*;
+        match self {
+            AccountLevel::Default => &[],
+            AccountLevel::Admin => &[
+                WritePosts,
+                EditAnyPost,
+                DeleteAnyPost,
+                ManageTags,
+                ModerateComments,
+                ManageUsers,
+            ],
+            AccountLevel::Editor => &[WritePosts, EditAnyPost, ManageTags, ModerateComments],
+            AccountLevel::Author => &[WritePosts],
+            AccountLevel::Moderator => &[ModerateComments],
+        }
+    }
+
+    pub fn can(self, capability: Capability) -> bool {
+        self.capabilities().contains(&capability)
+    }
 }
 
 #[derive(Queryable)]
@@ -17,6 +82,35 @@ pub struct User {
     pub email: String,
     pub nickname: String,
     pub permission: i32,
+    pub banned: bool,
+    /// End of a temporary ban. A ban without one lasts until lifted.
+    pub banned_until: Option<NaiveDateTime>,
+    /// Set by an admin to make the user pick a new password before they can
+    /// log in again.
+    pub password_reset_required: bool,
+    pub email_verified: bool,
+    pub bio: String,
+    pub avatar_url: Option<String>,
+    /// Links to the user's website and social media profiles.
+    pub links: Vec<String>,
+}
+
+impl User {
+    /// Whether the user is locked out by a ban right now.
+    pub fn is_banned(&self) -> bool {
+        self.banned
+            && self
+                .banned_until
+                .is_none_or(|until| until > Utc::now().naive_utc())
+    }
+
+    pub fn level(&self) -> AccountLevel {
+        AccountLevel::from_i32(self.permission)
+    }
+
+    pub fn can(&self, capability: Capability) -> bool {
+        self.level().can(capability)
+    }
 }
 
 #[derive(Queryable)]
@@ -25,10 +119,24 @@ pub struct Post {
     pub title: String,
     pub body: String,
     pub author: i32,
-    pub tags: String,
-    pub permission: i32,
     pub created_at: NaiveDateTime,
     pub modified_at: NaiveDateTime,
+    pub comments_closed: bool,
+    pub status: i32,
+    pub published_at: Option<NaiveDateTime>,
+    /// Number of the latest revision, bumped on every edit. Doubles as the
+    /// version clients must send back to edit the post.
+    pub revision: i32,
+    /// `body` rendered to sanitized HTML when the post was last saved.
+    pub body_html: Option<String>,
+    pub slug: String,
+    pub visibility: i32,
+    /// Argon2 hash of the password readers need when `visibility` is
+    /// `Password`.
+    pub password_hash: Option<String>,
+    /// Secret part of the share link that opens the post while it is
+    /// `Unlisted`.
+    pub share_token: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -47,15 +155,337 @@ pub struct NewPost<'a> {
     pub title: &'a str,
     pub body: &'a str,
     pub author: i32,
-    pub tags: &'a str,
-    pub permission: i32,
+    pub status: i32,
+    pub published_at: Option<NaiveDateTime>,
+    pub visibility: i32,
 }
 
-#[derive(Queryable, Clone, Serialize, Deserialize)]
+/// Who may read a post once it is published.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Everyone, and it is listed everywhere.
+    Public = 0,
+    /// Anyone with its share link. It is not listed anywhere.
+    Unlisted = 1,
+    /// Logged-in users only.
+    Members = 2,
+    /// Only its author and those who may edit any post.
+    Private = 3,
+    /// Anyone with its password. Only its header is listed and searched,
+    /// and it is kept out of feeds.
+    Password = 4,
+}
+
+impl Visibility {
+    pub fn from_i32(visibility: i32) -> Self {
+        match visibility {
+            0 => Visibility::Public,
+            1 => Visibility::Unlisted,
+            2 => Visibility::Members,
+            4 => Visibility::Password,
+            _ => Visibility::Private,
+        }
+    }
+}
+
+/// Where a post is in its lifecycle. Only `Published` posts are listed to
+/// readers; `Scheduled` ones are published once `published_at` passes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostStatus {
+    Draft = 0,
+    Scheduled = 1,
+    Published = 2,
+    Archived = 3,
+}
+
+impl PostStatus {
+    pub fn from_i32(status: i32) -> Self {
+        match status {
+            1 => PostStatus::Scheduled,
+            2 => PostStatus::Published,
+            3 => PostStatus::Archived,
+            _ => PostStatus::Draft,
+        }
+    }
+}
+
+/// The columns listings are built from; see `PostHeader`.
+#[derive(QueryableByName)]
+pub struct PostHeaderRow {
+    #[sql_type = "Int4"]
+    pub id: i32,
+    #[sql_type = "Text"]
+    pub slug: String,
+    #[sql_type = "Text"]
+    pub title: String,
+    #[sql_type = "Int4"]
+    pub author: i32,
+    #[sql_type = "Text"]
+    pub author_nickname: String,
+    #[sql_type = "Text"]
+    pub body: String,
+    #[sql_type = "Int4"]
+    pub visibility: i32,
+    #[sql_type = "Timestamp"]
+    pub created_at: NaiveDateTime,
+    #[sql_type = "Timestamp"]
+    pub modified_at: NaiveDateTime,
+    #[sql_type = "Nullable<Timestamp>"]
+    pub published_at: Option<NaiveDateTime>,
+    /// The value the listing is sorted by, as text; see `db::Cursor`.
+    #[sql_type = "Text"]
+    pub sort_key: String,
+}
+
+/// How a post listing is ordered. Dates and popularity (the number of
+/// approved comments) list the highest first, titles alphabetically.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostSort {
+    Created = 0,
+    Modified = 1,
+    #[default]
+    Published = 2,
+    Title = 3,
+    Popularity = 4,
+}
+
+impl PostSort {
+    pub fn from_i32(sort: i32) -> Option<Self> {
+        match sort {
+            0 => Some(PostSort::Created),
+            1 => Some(PostSort::Modified),
+            2 => Some(PostSort::Published),
+            3 => Some(PostSort::Title),
+            4 => Some(PostSort::Popularity),
+            _ => None,
+        }
+    }
+}
+
+/// A post as shown in listings.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PostHeader {
     pub id: i32,
+    pub slug: String,
     pub title: String,
     pub author: i32,
+    pub author_nickname: String,
+    pub tags: Vec<String>,
+    /// The start of the body as plain text. Left out for password-protected
+    /// posts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excerpt: Option<String>,
+    /// Estimated minutes it takes to read the body.
+    pub reading_time: i32,
+    pub visibility: Visibility,
+    pub created_at: NaiveDateTime,
+    pub modified_at: NaiveDateTime,
+    pub published_at: Option<NaiveDateTime>,
+}
+
+/// A snapshot of a post as it was saved. Revisions are numbered from 1 per
+/// post and never change once written.
+#[derive(Queryable, Clone, Serialize, Deserialize)]
+pub struct PostRevision {
+    pub revision: i32,
+    pub title: String,
+    pub body: String,
+    pub tags: Vec<String>,
+    pub editor: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Clone, Serialize, Deserialize)]
+pub struct RevisionHeader {
+    pub revision: i32,
+    pub title: String,
+    pub editor: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "post_revisions"]
+pub struct NewPostRevision<'a> {
+    pub post_id: i32,
+    pub revision: i32,
+    pub title: &'a str,
+    pub body: &'a str,
+    pub tags: &'a [String],
+    pub editor: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "post_slug_redirects"]
+pub struct SlugRedirect<'a> {
+    pub slug: &'a str,
+    pub post_id: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "tags"]
+pub struct NewTag<'a> {
+    pub name: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "post_tags"]
+pub struct PostTag {
+    pub post_id: i32,
+    pub tag_id: i32,
+}
+
+#[derive(Queryable, Clone, Serialize, Deserialize)]
+pub struct TagCount {
+    pub name: String,
+    pub count: i64,
+}
+
+/// A login session. Its `id` doubles as the `jti` of every access token
+/// issued for it, so revoking the row invalidates those tokens too.
+#[derive(Queryable)]
+pub struct RefreshToken {
+    pub id: String,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "refresh_tokens"]
+pub struct NewRefreshToken<'a> {
+    pub id: &'a str,
+    pub user_id: i32,
+    pub token_hash: &'a str,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+/// A single-use code letting someone register while registration is
+/// invite-only.
+#[derive(Queryable, Clone, Serialize, Deserialize)]
+pub struct Invite {
+    pub code: String,
+    pub created_by: Option<i32>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_by: Option<i32>,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "invites"]
+pub struct NewInvite<'a> {
+    pub code: &'a str,
+    pub created_by: i32,
+    pub expires_at: NaiveDateTime,
+}
+
+/// What a one-time token sent by mail lets its holder do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    VerifyEmail = 0,
+    ResetPassword = 1,
+}
+
+/// A token mailed to `email`. It can be redeemed once, before `expires_at`.
+#[derive(Insertable)]
+#[table_name = "one_time_tokens"]
+pub struct NewOneTimeToken<'a> {
+    pub id: &'a str,
+    pub user_id: i32,
+    pub purpose: i32,
+    pub email: &'a str,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentStatus {
+    Pending = 0,
+    Approved = 1,
+    Spam = 2,
+    Deleted = 3,
+}
+
+impl CommentStatus {
+    pub fn from_i32(status: i32) -> Self {
+        match status {
+            1 => CommentStatus::Approved,
+            2 => CommentStatus::Spam,
+            3 => CommentStatus::Deleted,
+            _ => CommentStatus::Pending,
+        }
+    }
+}
+
+/// A comment on a post, written either by a user (`author`) or anonymously
+/// under `author_name`.
+#[derive(Queryable)]
+pub struct Comment {
+    pub id: i32,
+    pub post_id: i32,
+    pub parent_id: Option<i32>,
+    pub author: Option<i32>,
+    pub author_name: Option<String>,
+    pub body: String,
+    pub status: i32,
     pub created_at: NaiveDateTime,
     pub modified_at: NaiveDateTime,
 }
+
+#[derive(Insertable)]
+#[table_name = "comments"]
+pub struct NewComment<'a> {
+    pub post_id: i32,
+    pub parent_id: Option<i32>,
+    pub author: Option<i32>,
+    pub author_name: Option<&'a str>,
+    pub body: &'a str,
+    pub status: i32,
+}
+
+/// The columns search results are built from. `post.sort_key` holds the
+/// rank as text.
+#[derive(QueryableByName)]
+pub struct SearchHitRow {
+    #[diesel(embed)]
+    pub post: PostHeaderRow,
+    #[sql_type = "Float4"]
+    pub rank: f32,
+    #[sql_type = "Text"]
+    pub snippet: String,
+}
+
+/// A post found by search: its listing header with how well and where it
+/// matched.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub post: PostHeader,
+    pub rank: f32,
+    /// HTML-escaped excerpt of the body with matches wrapped in `<mark>`,
+    /// empty for a password-protected post.
+    pub snippet: String,
+}
+
+/// The number of posts published in a month.
+#[derive(QueryableByName, Clone, Serialize, Deserialize)]
+pub struct ArchiveMonth {
+    #[sql_type = "Int4"]
+    pub year: i32,
+    #[sql_type = "Int4"]
+    pub month: i32,
+    #[sql_type = "BigInt"]
+    pub count: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct Count {
+    #[sql_type = "BigInt"]
+    pub count: i64,
+}
@@ -1,13 +1,102 @@
+table! {
+    comments (id) {
+        id -> Int4,
+        post_id -> Int4,
+        parent_id -> Nullable<Int4>,
+        author -> Nullable<Int4>,
+        author_name -> Nullable<Varchar>,
+        body -> Varchar,
+        status -> Int4,
+        created_at -> Timestamp,
+        modified_at -> Timestamp,
+    }
+}
+
+table! {
+    invites (code) {
+        code -> Varchar,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_by -> Nullable<Int4>,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    one_time_tokens (id) {
+        id -> Varchar,
+        user_id -> Int4,
+        purpose -> Int4,
+        email -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    post_revisions (id) {
+        id -> Int4,
+        post_id -> Int4,
+        revision -> Int4,
+        title -> Varchar,
+        body -> Varchar,
+        tags -> Array<Text>,
+        editor -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    post_slug_redirects (slug) {
+        slug -> Varchar,
+        post_id -> Int4,
+    }
+}
+
+table! {
+    post_tags (post_id, tag_id) {
+        post_id -> Int4,
+        tag_id -> Int4,
+    }
+}
+
 table! {
     posts (id) {
         id -> Int4,
         title -> Varchar,
         body -> Varchar,
         author -> Int4,
-        tags -> Varchar,
-        permission -> Int4,
         created_at -> Timestamp,
         modified_at -> Timestamp,
+        comments_closed -> Bool,
+        status -> Int4,
+        published_at -> Nullable<Timestamp>,
+        revision -> Int4,
+        body_html -> Nullable<Varchar>,
+        slug -> Varchar,
+        visibility -> Int4,
+        password_hash -> Nullable<Varchar>,
+        share_token -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    refresh_tokens (id) {
+        id -> Varchar,
+        user_id -> Int4,
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    tags (id) {
+        id -> Int4,
+        name -> Varchar,
     }
 }
 
@@ -19,7 +108,36 @@ table! {
         email -> Varchar,
         nickname -> Varchar,
         permission -> Int4,
+        banned -> Bool,
+        banned_until -> Nullable<Timestamp>,
+        password_reset_required -> Bool,
+        email_verified -> Bool,
+        bio -> Varchar,
+        avatar_url -> Nullable<Varchar>,
+        links -> Array<Text>,
     }
 }
 
-allow_tables_to_appear_in_same_query!(posts, users,);
+joinable!(comments -> posts (post_id));
+joinable!(comments -> users (author));
+joinable!(one_time_tokens -> users (user_id));
+joinable!(post_revisions -> posts (post_id));
+joinable!(post_revisions -> users (editor));
+joinable!(post_slug_redirects -> posts (post_id));
+joinable!(post_tags -> posts (post_id));
+joinable!(post_tags -> tags (tag_id));
+joinable!(posts -> users (author));
+joinable!(refresh_tokens -> users (user_id));
+
+allow_tables_to_appear_in_same_query!(
+    comments,
+    invites,
+    one_time_tokens,
+    post_revisions,
+    post_slug_redirects,
+    post_tags,
+    posts,
+    refresh_tokens,
+    tags,
+    users,
+);
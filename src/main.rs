@@ -1,12 +1,21 @@
 extern crate actix_files;
 extern crate actix_web;
+extern crate ammonia;
+extern crate argon2;
+extern crate deunicode;
 extern crate env_logger;
 extern crate hex;
 extern crate hmac;
 extern crate jwt_simple;
+extern crate lettre;
+extern crate log;
+extern crate pulldown_cmark;
+extern crate rand_core;
 extern crate serde;
 extern crate serde_json;
 extern crate sha3;
+extern crate similar;
+extern crate syntect;
 extern crate toml;
 #[macro_use]
 extern crate diesel;
@@ -14,12 +23,12 @@ extern crate diesel;
 extern crate lazy_static;
 
 mod api;
+mod bootstrap;
 mod config;
 mod db;
 mod middlewares;
 
-use actix_files::Files;
-use actix_web::{middleware, App, HttpServer};
+use actix_web::{middleware, web, App, HttpServer};
 
 use config::*;
 
@@ -30,26 +39,99 @@ lazy_static! {
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let config = CONFIG.clone();
-    std::env::set_var("RUST_LOG", "actix_web=info");
+    std::env::set_var("RUST_LOG", "actix_web=info,blog_backend=info");
     env_logger::init();
 
-    HttpServer::new(|| {
+    let pool = middlewares::postgresql::build_pool(&config.server);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("create-admin") {
+        if let Err(e) = bootstrap::create_admin_command(&pool, &args[1..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    bootstrap::check_timezone(&pool)?;
+    bootstrap::admin_from_env(&pool);
+    bootstrap::backfill_slugs(&pool);
+    let mailer = middlewares::mailer::from_config(&config.mail)
+        .map(web::Data::new)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    middlewares::scheduler::spawn_publisher(pool.clone(), config.server.publish_interval);
+
+    HttpServer::new(move || {
         App::new()
+            .data(pool.clone())
+            .app_data(mailer.clone())
+            .app_data(web::JsonConfig::default().error_handler(|err, _| {
+                api::errors::ApiError::InvalidInput(err.to_string()).into()
+            }))
+            .app_data(web::QueryConfig::default().error_handler(|err, _| {
+                api::errors::ApiError::InvalidInput(err.to_string()).into()
+            }))
             .wrap(middleware::Logger::default())
             .service(api::account_service::ping)
             .service(api::account_service::login)
+            .service(api::account_service::refresh)
+            .service(api::account_service::logout)
+            .service(api::account_service::logout_all)
             .service(api::account_service::register)
+            .service(api::account_service::request_verification)
+            .service(api::account_service::verify_email)
+            .service(api::account_service::request_password_reset)
+            .service(api::account_service::reset_password)
+            .service(api::account_service::change_password)
+            .service(api::account_service::change_email)
+            .service(api::account_service::edit_profile)
             .service(api::account_service::info)
             .service(api::account_service::get_user)
+            .service(api::account_service::levels)
+            .service(api::account_service::set_level)
+            .service(api::account_service::users)
+            .service(api::account_service::ban_user)
+            .service(api::account_service::unban_user)
+            .service(api::account_service::force_password_reset)
+            .service(api::account_service::delete_user)
+            .service(api::account_service::new_invite)
+            .service(api::account_service::invites)
+            .service(api::account_service::delete_invite)
             .service(api::blog_service::count_posts)
             .service(api::blog_service::new_post)
             .service(api::blog_service::view_post)
+            .service(api::blog_service::post_by_slug)
+            .service(api::blog_service::shared_post)
+            .service(api::blog_service::set_post_status)
+            .service(api::blog_service::drafts)
             .service(api::blog_service::delete_post)
             .service(api::blog_service::recent_posts)
             .service(api::blog_service::edit_post)
+            .service(api::blog_service::revisions)
+            .service(api::blog_service::view_revision)
+            .service(api::blog_service::revision_diff)
+            .service(api::blog_service::restore_revision)
             .service(api::blog_service::posts)
+            .service(api::blog_service::tags)
+            .service(api::blog_service::tag_posts)
+            .service(api::blog_service::author_profile)
+            .service(api::blog_service::author_posts)
+            .service(api::blog_service::archive)
+            .service(api::blog_service::archive_year)
+            .service(api::blog_service::archive_month)
+            .service(api::blog_service::rename_tag)
+            .service(api::blog_service::search)
+            .service(api::blog_service::highlight_css)
+            .service(api::blog_service::highlight_themes)
+            .service(api::comment_service::comments)
+            .service(api::comment_service::new_comment)
+            .service(api::comment_service::edit_comment)
+            .service(api::comment_service::delete_comment)
+            .service(api::comment_service::comment_queue)
+            .service(api::comment_service::moderate_comments)
+            .service(api::comment_service::close_comments)
+            .service(api::feed_service::rss)
+            .service(api::feed_service::atom)
     })
-    .bind(&format!("{}:{}", config.server.host, config.server.port))?
+    .bind(format!("{}:{}", config.server.host, config.server.port))?
     .run()
     .await
 }
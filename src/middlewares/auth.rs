@@ -0,0 +1,277 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::dev::Payload;
+use actix_web::http::header;
+use actix_web::{web, FromRequest, HttpRequest};
+use chrono::{NaiveDateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use jwt_simple::prelude::*;
+use rand_core::{OsRng, RngCore};
+use sha3::{Digest, Sha3_256};
+
+use crate::api::account_service::AccountToken;
+use crate::api::errors::ApiError;
+use crate::db;
+use crate::db::models::{Capability, NewOneTimeToken, TokenPurpose, User};
+use crate::middlewares::postgresql::{run, DbPool};
+use crate::CONFIG;
+
+/// The caller identified by a valid `Authorization: Bearer <token>` header.
+pub struct AuthenticatedUser(pub User);
+
+/// Like `AuthenticatedUser`, but anonymous callers (no header, or an expired
+/// or otherwise invalid token) are let through as `None`.
+pub struct OptionalUser(pub Option<User>);
+
+/// What a reader presents to open a post that is not public: the
+/// `X-Post-Password` header for password-protected posts and the
+/// `X-Share-Token` header for unlisted ones.
+#[derive(Clone, Debug, Default)]
+pub struct PostKey {
+    pub password: Option<String>,
+    pub share_token: Option<String>,
+}
+
+/// The caller together with the session (`jti`) their access token belongs to.
+pub struct AuthenticatedSession {
+    pub user: User,
+    pub id: String,
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?.trim();
+    if token.is_empty() {
+        None
+    } else {
+        Some(token.to_string())
+    }
+}
+
+/// An access/refresh token pair for one session.
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+pub fn random_hex(len: usize) -> String {
+    let mut buf = vec![0u8; len];
+    OsRng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+fn hash_refresh_token(token: &str) -> String {
+    let mut hasher = Sha3_256::new();
+    hasher.update(token.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+fn refresh_expiry() -> NaiveDateTime {
+    let days = CONFIG.security.refresh_token_ttl_days as i64;
+    Utc::now().naive_utc() + chrono::Duration::days(days)
+}
+
+fn access_token(user_id: i32, session_id: &str) -> String {
+    let config = CONFIG.clone();
+    let key = HS256Key::from_bytes(config.secret.secret.as_bytes());
+    let auth = AccountToken { pk: user_id };
+    let claims = Claims::with_custom_claims(
+        auth,
+        Duration::from_mins(config.security.access_token_ttl_minutes),
+    )
+    .with_jwt_id(session_id);
+    key.authenticate(claims).unwrap()
+}
+
+/// Opens a new session for `user_id`. Refresh tokens have the form
+/// `<session id>.<secret>`; only a hash of the whole token is stored.
+pub fn start_session(db: &PgConnection, user_id: i32) -> QueryResult<TokenPair> {
+    let session_id = random_hex(16);
+    let refresh_token = format!("{}.{}", session_id, random_hex(32));
+    db::create_refresh_token(
+        db,
+        &session_id,
+        user_id,
+        &hash_refresh_token(&refresh_token),
+        refresh_expiry(),
+    )?;
+    Ok(TokenPair {
+        access_token: access_token(user_id, &session_id),
+        refresh_token,
+    })
+}
+
+/// Exchanges a refresh token for a new pair, invalidating the old one.
+pub fn refresh_session(db: &PgConnection, refresh_token: &str) -> QueryResult<Option<TokenPair>> {
+    let session_id = match refresh_token.split('.').next() {
+        Some(id) if !id.is_empty() => id,
+        _ => return Ok(None),
+    };
+    let new_token = format!("{}.{}", session_id, random_hex(32));
+    let rotated = db::rotate_refresh_token(
+        db,
+        session_id,
+        &hash_refresh_token(refresh_token),
+        &hash_refresh_token(&new_token),
+        refresh_expiry(),
+    )?;
+    Ok(rotated.map(|session| TokenPair {
+        access_token: access_token(session.user_id, &session.id),
+        refresh_token: new_token,
+    }))
+}
+
+#[derive(Serialize, Deserialize)]
+struct OneTimeClaims {
+    purpose: TokenPurpose,
+}
+
+/// Issues a token for `purpose` to be mailed to `email`, valid for
+/// `ttl_secs`. It is signed like access tokens, and its `jti` names a row in
+/// `one_time_tokens` so that it can only be redeemed once.
+pub fn issue_one_time_token(
+    db: &PgConnection,
+    user_id: i32,
+    purpose: TokenPurpose,
+    email: &str,
+    ttl_secs: u64,
+) -> QueryResult<String> {
+    let id = random_hex(16);
+    db::create_one_time_token(
+        db,
+        &NewOneTimeToken {
+            id: &id,
+            user_id,
+            purpose: purpose as i32,
+            email,
+            expires_at: Utc::now().naive_utc() + chrono::Duration::seconds(ttl_secs as i64),
+        },
+    )?;
+    let key = HS256Key::from_bytes(CONFIG.secret.secret.as_bytes());
+    let claims =
+        Claims::with_custom_claims(OneTimeClaims { purpose }, Duration::from_secs(ttl_secs))
+            .with_jwt_id(&id);
+    Ok(key.authenticate(claims).unwrap())
+}
+
+/// Uses up `token` if it is a valid, unused token for `purpose`, returning
+/// the user and the email it was sent to.
+pub fn redeem_one_time_token(
+    db: &PgConnection,
+    token: &str,
+    purpose: TokenPurpose,
+) -> QueryResult<Option<(i32, String)>> {
+    let key = HS256Key::from_bytes(CONFIG.secret.secret.as_bytes());
+    let claims = match key.verify_token::<OneTimeClaims>(token, None) {
+        Ok(claims) if claims.custom.purpose == purpose => claims,
+        _ => return Ok(None),
+    };
+    match claims.jwt_id {
+        Some(id) => db::redeem_one_time_token(db, &id, purpose),
+        None => Ok(None),
+    }
+}
+
+async fn load_session(
+    pool: Option<web::Data<DbPool>>,
+    token: String,
+) -> Result<AuthenticatedSession, ApiError> {
+    let config = CONFIG.clone();
+    let key = HS256Key::from_bytes(config.secret.secret.as_bytes());
+    let claims = key
+        .verify_token::<AccountToken>(&token, None)
+        .map_err(|_| ApiError::Unauthorized)?;
+    let id = claims.jwt_id.ok_or(ApiError::Unauthorized)?;
+    let pool = pool.ok_or(ApiError::DatabaseError)?;
+    let pk = claims.custom.pk;
+    let session = id.clone();
+    let user = run(&pool, move |db| db::session_user(db, &session, pk))
+        .await?
+        .filter(|user| !user.is_banned())
+        .ok_or(ApiError::Unauthorized)?;
+    Ok(AuthenticatedSession { user, id })
+}
+
+impl FromRequest for AuthenticatedSession {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let pool = req.app_data::<web::Data<DbPool>>().cloned();
+        let token = bearer_token(req);
+        Box::pin(async move {
+            let token = token.ok_or(ApiError::Unauthorized)?;
+            load_session(pool, token).await
+        })
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let pool = req.app_data::<web::Data<DbPool>>().cloned();
+        let token = bearer_token(req);
+        Box::pin(async move {
+            let token = token.ok_or(ApiError::Unauthorized)?;
+            load_session(pool, token)
+                .await
+                .map(|session| AuthenticatedUser(session.user))
+        })
+    }
+}
+
+impl FromRequest for OptionalUser {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let pool = req.app_data::<web::Data<DbPool>>().cloned();
+        let token = bearer_token(req);
+        Box::pin(async move {
+            match token {
+                Some(token) => match load_session(pool, token).await {
+                    Ok(session) => Ok(OptionalUser(Some(session.user))),
+                    Err(ApiError::Unauthorized) => Ok(OptionalUser(None)),
+                    Err(e) => Err(e),
+                },
+                None => Ok(OptionalUser(None)),
+            }
+        })
+    }
+}
+
+/// Fails with `Forbidden` unless `user` has `capability`.
+pub fn require(user: &User, capability: Capability) -> Result<(), ApiError> {
+    if user.can(capability) {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden)
+    }
+}
+
+impl FromRequest for PostKey {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let value = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let key = PostKey {
+            password: value("X-Post-Password"),
+            share_token: value("X-Share-Token"),
+        };
+        Box::pin(async move { Ok(key) })
+    }
+}
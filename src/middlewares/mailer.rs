@@ -0,0 +1,165 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use actix_web::{error::BlockingError, web};
+use chrono::Utc;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+
+use crate::config::{MailConfig, MailTransport};
+use crate::middlewares::auth::random_hex;
+
+/// A plain-text mail to a single recipient.
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub struct MailError(String);
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mail error: {}", self.0)
+    }
+}
+
+impl std::error::Error for MailError {}
+
+fn mail_error<E: ToString>(e: E) -> MailError {
+    MailError(e.to_string())
+}
+
+/// Delivers mails. Sending may block, so call it through `send`.
+pub trait Mailer: Send + Sync {
+    fn send(&self, mail: &Mail) -> Result<(), MailError>;
+}
+
+pub type BoxedMailer = Box<dyn Mailer>;
+
+fn message(from: &str, mail: &Mail) -> Result<Message, MailError> {
+    Message::builder()
+        .from(from.parse().map_err(mail_error)?)
+        .to(mail.to.parse().map_err(mail_error)?)
+        .subject(mail.subject.as_str())
+        .header(ContentType::TEXT_PLAIN)
+        .body(mail.body.clone())
+        .map_err(mail_error)
+}
+
+/// Sends through an SMTP relay.
+pub struct SmtpMailer {
+    from: String,
+    transport: SmtpTransport,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &MailConfig) -> Result<Self, MailError> {
+        let builder = if config.smtp_implicit_tls {
+            SmtpTransport::relay(&config.smtp_host)
+        } else {
+            SmtpTransport::starttls_relay(&config.smtp_host)
+        };
+        let mut builder = builder.map_err(mail_error)?.port(config.smtp_port);
+        if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(SmtpMailer {
+            from: config.from.clone(),
+            transport: builder.build(),
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        self.transport
+            .send(&message(&self.from, mail)?)
+            .map(|_| ())
+            .map_err(mail_error)
+    }
+}
+
+/// Writes every mail as an `.eml` file into a directory, for local
+/// development and tests.
+pub struct FileMailer {
+    from: String,
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(config: &MailConfig) -> Self {
+        FileMailer {
+            from: config.from.clone(),
+            dir: PathBuf::from(&config.outbox),
+        }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        let message = message(&self.from, mail)?;
+        fs::create_dir_all(&self.dir).map_err(mail_error)?;
+        let name = format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%d%H%M%S%.6f"),
+            random_hex(4)
+        );
+        fs::write(self.dir.join(name), message.formatted()).map_err(mail_error)
+    }
+}
+
+pub fn from_config(config: &MailConfig) -> Result<BoxedMailer, MailError> {
+    Ok(match config.transport {
+        MailTransport::Smtp => Box::new(SmtpMailer::new(config)?),
+        MailTransport::File => Box::new(FileMailer::new(config)),
+    })
+}
+
+/// Sends `mail` on the blocking thread pool.
+pub async fn send(mailer: &web::Data<BoxedMailer>, mail: Mail) -> Result<(), MailError> {
+    let mailer = mailer.clone();
+    web::block(move || mailer.send(&mail))
+        .await
+        .map_err(|e| match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => MailError(String::from("mail task canceled")),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_mailer_writes_eml() {
+        let dir = std::env::temp_dir().join(format!("blog-outbox-{}", random_hex(8)));
+        let config = MailConfig {
+            from: String::from("Blog <blog@example.com>"),
+            outbox: dir.to_string_lossy().into_owned(),
+            ..MailConfig::default()
+        };
+        let mail = Mail {
+            to: String::from("reader@example.com"),
+            subject: String::from("Reset your password"),
+            body: String::from("Follow the link to reset it."),
+        };
+        FileMailer::new(&config).send(&mail).unwrap();
+
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let eml = fs::read_to_string(&files[0]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(eml.contains("From: Blog <blog@example.com>\r\n"));
+        assert!(eml.contains("To: reader@example.com\r\n"));
+        assert!(eml.contains("Subject: Reset your password\r\n"));
+        assert!(eml.contains("Follow the link to reset it."));
+    }
+}
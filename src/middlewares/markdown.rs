@@ -0,0 +1,238 @@
+use ammonia::Builder;
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use syntect::highlighting::ThemeSet;
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+use crate::CONFIG;
+
+/// Prepended to footnote names so the ids they turn into cannot clash with
+/// ids used by the page around the post.
+const FOOTNOTE_PREFIX: &str = "fn-";
+
+/// Highlighted code is marked up with `hl-` classes, styled by the
+/// stylesheet from `theme_css`.
+const HIGHLIGHT_PREFIX: &str = "hl-";
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed {
+    prefix: HIGHLIGHT_PREFIX,
+};
+
+lazy_static! {
+    static ref SYNTAXES: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static ref THEMES: ThemeSet = ThemeSet::load_defaults();
+    static ref SANITIZER: Builder<'static> = {
+        let mut builder = Builder::default();
+        builder
+            .add_tags(&["input"])
+            .add_tag_attribute_values("input", "type", &["checkbox"])
+            .add_tag_attributes("input", &["checked", "disabled"])
+            .add_tag_attributes("code", &["class"])
+            .add_tag_attributes("span", &["class"])
+            .add_allowed_classes("pre", &["hl-code"])
+            .add_tag_attributes("div", &["id"])
+            .add_allowed_classes("div", &["footnote-definition"])
+            .add_allowed_classes("sup", &["footnote-reference", "footnote-definition-label"])
+            .attribute_filter(|element, attribute, value| match (element, attribute) {
+                ("code", "class") => language_class(value).then(|| value.into()),
+                ("span", "class") => value
+                    .split_whitespace()
+                    .all(|class| class.starts_with(HIGHLIGHT_PREFIX))
+                    .then(|| value.into()),
+                ("div", "id") => value.starts_with(FOOTNOTE_PREFIX).then(|| value.into()),
+                _ => Some(value.into()),
+            });
+        for cell in &["th", "td"] {
+            builder.add_tag_attribute_values(
+                cell,
+                "style",
+                &[
+                    "text-align: left",
+                    "text-align: center",
+                    "text-align: right",
+                ],
+            );
+        }
+        builder
+    };
+}
+
+fn language_class(class: &str) -> bool {
+    match class.strip_prefix("language-") {
+        Some(lang) => lang
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '+'),
+        None => false,
+    }
+}
+
+fn prefixed(name: CowStr) -> CowStr {
+    format!("{}{}", FOOTNOTE_PREFIX, name).into()
+}
+
+/// The language named first in a fence's info string, e.g. `rust` for
+/// `rust,ignore`.
+fn fence_language(info: &str) -> &str {
+    info.split(|c: char| c.is_whitespace() || c == ',')
+        .next()
+        .unwrap_or_default()
+}
+
+/// The syntax to highlight a fenced block with, if highlighting is on and
+/// its language is known.
+fn syntax_for(lang: &str) -> Option<&'static SyntaxReference> {
+    if !CONFIG.blog.highlight_code || !language_class(&format!("language-{}", lang)) {
+        return None;
+    }
+    SYNTAXES.find_syntax_by_token(lang)
+}
+
+fn highlight(lang: &str, syntax: &SyntaxReference, code: &str) -> Option<String> {
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        generator
+            .parse_html_for_line_which_includes_newline(line)
+            .ok()?;
+    }
+    Some(format!(
+        "<pre class=\"{}code\"><code class=\"language-{}\">{}</code></pre>\n",
+        HIGHLIGHT_PREFIX,
+        lang,
+        generator.finalize()
+    ))
+}
+
+/// Renders a post body written in CommonMark with the GFM extensions
+/// (tables, task lists, footnotes, strikethrough) to sanitized HTML. Fenced
+/// code in a known language is highlighted when `blog.highlight_code` is on;
+/// anything else is left as a plain code block.
+pub fn render(source: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH;
+    let mut events = Vec::new();
+    // Info string, syntax and text of the code block being collected.
+    let mut block: Option<(CowStr, &SyntaxReference, String)> = None;
+    for event in Parser::new_ext(source, options) {
+        match (event, &mut block) {
+            (Event::Text(text), Some((_, _, code))) => code.push_str(&text),
+            (Event::End(TagEnd::CodeBlock), Some(_)) => {
+                let (info, syntax, code) = block.take().unwrap();
+                match highlight(fence_language(&info), syntax, &code) {
+                    Some(html) => events.push(Event::Html(html.into())),
+                    None => events.extend(vec![
+                        Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))),
+                        Event::Text(code.into()),
+                        Event::End(TagEnd::CodeBlock),
+                    ]),
+                }
+            }
+            (Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))), None) => {
+                match syntax_for(fence_language(&info)) {
+                    Some(syntax) => block = Some((info, syntax, String::new())),
+                    None => events.push(Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))),
+                }
+            }
+            (Event::FootnoteReference(name), _) => {
+                events.push(Event::FootnoteReference(prefixed(name)))
+            }
+            (Event::Start(Tag::FootnoteDefinition(name)), _) => {
+                events.push(Event::Start(Tag::FootnoteDefinition(prefixed(name))))
+            }
+            (event, _) => events.push(event),
+        }
+    }
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events.into_iter());
+    SANITIZER.clean(&unsafe_html).to_string()
+}
+
+/// The text of a post body with its markup stripped and whitespace
+/// collapsed.
+pub fn plain_text(source: &str) -> String {
+    let mut text = String::new();
+    for event in Parser::new_ext(
+        source,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    ) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak
+            | Event::HardBreak
+            | Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::Item
+                | TagEnd::CodeBlock
+                | TagEnd::TableCell,
+            ) => text.push(' '),
+            _ => {}
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// `text` cut to its first `limit` characters at a word boundary, with an
+/// ellipsis when anything was cut.
+pub fn truncate(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(limit).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(i) if i > 0 => &cut[..i],
+        _ => &cut[..],
+    };
+    format!("{}…", cut.trim_end())
+}
+
+/// Minutes it takes to read `text` at `words_per_minute`, at least one.
+pub fn reading_time(text: &str, words_per_minute: usize) -> i32 {
+    let words = text.split_whitespace().count();
+    let minutes = words.div_ceil(words_per_minute.max(1));
+    minutes.max(1) as i32
+}
+
+/// Names of the themes `theme_css` knows.
+pub fn theme_names() -> Vec<String> {
+    THEMES.themes.keys().cloned().collect()
+}
+
+/// A stylesheet colouring highlighted code with `theme`.
+pub fn theme_css(theme: &str) -> Option<String> {
+    css_for_theme_with_class_style(THEMES.themes.get(theme)?, CLASS_STYLE).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_scripts() {
+        let html = render("hi <script>alert(1)</script>");
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("alert(1)"));
+    }
+
+    #[test]
+    fn strips_javascript_links() {
+        let html = render("[click](javascript:alert(1))");
+        assert!(html.contains("click"));
+        assert!(!html.contains("javascript:"));
+    }
+
+    #[test]
+    fn keeps_only_highlight_span_classes() {
+        let html = render("<span class=\"hl-keyword\">a</span><span class=\"evil\">b</span>");
+        assert!(html.contains("<span class=\"hl-keyword\">a</span>"));
+        assert!(html.contains("<span>b</span>"));
+    }
+
+    #[test]
+    fn keeps_only_footnote_div_ids() {
+        let html = render("<div id=\"fn-1\">a</div>\n\n<div id=\"main\">b</div>");
+        assert!(html.contains("<div id=\"fn-1\">"));
+        assert!(!html.contains("id=\"main\""));
+    }
+}
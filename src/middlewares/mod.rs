@@ -1 +1,7 @@
+pub mod auth;
+pub mod mailer;
+pub mod markdown;
+pub mod password;
 pub mod postgresql;
+pub mod scheduler;
+pub mod slug;
@@ -0,0 +1,83 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand_core::OsRng;
+use sha3::{Digest, Sha3_256};
+use std::convert::TryFrom;
+
+use crate::CONFIG;
+
+pub use argon2::password_hash::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verification {
+    Invalid,
+    Valid,
+    /// The password matched, but the stored hash is a legacy SHA3 digest or
+    /// uses different Argon2 parameters than `[security]` and should be replaced.
+    NeedsRehash,
+}
+
+fn hasher() -> Result<Argon2<'static>, Error> {
+    let config = CONFIG.clone();
+    let params = Params::new(
+        config.security.argon2_memory_kib,
+        config.security.argon2_iterations,
+        config.security.argon2_parallelism,
+        None,
+    )?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+/// Hashes `pass` into a PHC string (`$argon2id$v=19$m=...`) with a fresh salt.
+pub fn hash(pass: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(hasher()?.hash_password(pass.as_bytes(), &salt)?.to_string())
+}
+
+pub fn verify(pass: &str, stored: &str) -> Result<Verification, Error> {
+    let parsed = match PasswordHash::new(stored) {
+        Ok(parsed) => parsed,
+        Err(_) => return Ok(verify_legacy(pass, stored)),
+    };
+    let argon2 = hasher()?;
+    if argon2.verify_password(pass.as_bytes(), &parsed).is_err() {
+        return Ok(Verification::Invalid);
+    }
+    let current = argon2.params();
+    let outdated = parsed.algorithm != Algorithm::Argon2id.ident()
+        || Params::try_from(&parsed)
+            .map(|p| {
+                p.m_cost() != current.m_cost()
+                    || p.t_cost() != current.t_cost()
+                    || p.p_cost() != current.p_cost()
+            })
+            .unwrap_or(true);
+    Ok(if outdated {
+        Verification::NeedsRehash
+    } else {
+        Verification::Valid
+    })
+}
+
+/// Accounts created before Argon2 store a bare hex-encoded SHA3-256 digest.
+fn verify_legacy(pass: &str, stored: &str) -> Verification {
+    let mut hasher = Sha3_256::new();
+    hasher.update(pass.as_bytes());
+    if hex::encode(hasher.finalize()) == stored {
+        Verification::NeedsRehash
+    } else {
+        Verification::Invalid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_legacy_digests() {
+        let stored = hex::encode(Sha3_256::digest(b"hunter2"));
+        assert_eq!(verify("hunter2", &stored), Ok(Verification::NeedsRehash));
+        assert_eq!(verify("hunter3", &stored), Ok(Verification::Invalid));
+    }
+}
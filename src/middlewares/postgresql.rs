@@ -1,12 +1,60 @@
+use std::fmt;
+use std::time::Duration;
+
+use actix_web::{error::BlockingError, web};
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PoolError};
+
+use crate::config::ServerConfig;
+
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+
+#[derive(Debug)]
+pub enum DbError {
+    Pool(PoolError),
+    Query(diesel::result::Error),
+    Canceled,
+}
 
-use crate::CONFIG;
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Pool(e) => write!(f, "connection pool error: {}", e),
+            DbError::Query(e) => write!(f, "query error: {}", e),
+            DbError::Canceled => write!(f, "database task canceled"),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+/// Builds the pool without connecting eagerly, so an unreachable database
+/// shows up as `DatabaseError` responses instead of keeping the server down.
+pub fn build_pool(config: &ServerConfig) -> DbPool {
+    let manager = ConnectionManager::<PgConnection>::new(config.database_url.as_str());
+    Pool::builder()
+        .max_size(config.pool_max_size)
+        .min_idle(config.pool_min_idle)
+        .connection_timeout(Duration::from_secs(config.pool_connection_timeout))
+        .idle_timeout(Some(Duration::from_secs(config.pool_idle_timeout)))
+        .build_unchecked(manager)
+}
 
-pub fn establish_connection() -> PgConnection {
-    let config = CONFIG.clone();
-    PgConnection::establish(&config.server.database_url).expect(&format!(
-        "Error connecting to {}",
-        config.server.database_url
-    ))
+/// Checks a connection out of `pool` and runs `f` on the blocking thread pool.
+pub async fn run<F, T>(pool: &DbPool, f: F) -> Result<T, DbError>
+where
+    F: FnOnce(&PgConnection) -> QueryResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = pool.clone();
+    web::block(move || {
+        let db = pool.get().map_err(DbError::Pool)?;
+        f(&db).map_err(DbError::Query)
+    })
+    .await
+    .map_err(|e| match e {
+        BlockingError::Error(e) => e,
+        BlockingError::Canceled => DbError::Canceled,
+    })
 }
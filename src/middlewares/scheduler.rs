@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+use actix_web::rt;
+
+use crate::db;
+use crate::middlewares::postgresql::{run, DbPool};
+
+/// Publishes scheduled posts that are due, checking every `interval`
+/// seconds for as long as the server runs.
+pub fn spawn_publisher(pool: DbPool, interval: u64) {
+    rt::spawn(async move {
+        let mut ticks = rt::time::interval(Duration::from_secs(interval.max(1)));
+        loop {
+            ticks.tick().await;
+            match run(&pool, db::publish_due_posts).await {
+                Ok(0) => {}
+                Ok(count) => log::info!("published {} scheduled post(s)", count),
+                Err(e) => log::error!("publishing scheduled posts failed: {}", e),
+            }
+        }
+    });
+}
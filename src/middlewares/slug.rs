@@ -0,0 +1,47 @@
+use deunicode::deunicode;
+
+const MAX_LENGTH: usize = 80;
+
+/// Lowercase ASCII words of `text` joined by dashes, transliterating
+/// anything else first, e.g. `annyeonghaseyo-segye` for `안녕하세요 세계`.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in deunicode(text).chars() {
+        if slug.len() >= MAX_LENGTH {
+            break;
+        }
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    match slug.trim_end_matches('-') {
+        "" => String::from("post"),
+        slug => slug.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transliterates() {
+        assert_eq!(slugify("안녕하세요 세계"), "annyeonghaseyo-segye");
+        assert_eq!(slugify("  Hello, World!  "), "hello-world");
+    }
+
+    #[test]
+    fn falls_back_without_words() {
+        assert_eq!(slugify(""), "post");
+        assert_eq!(slugify("?!- ..."), "post");
+    }
+
+    #[test]
+    fn caps_length() {
+        let slug = slugify(&"word ".repeat(100));
+        assert!(slug.len() <= MAX_LENGTH);
+        assert!(!slug.ends_with('-'));
+    }
+}